@@ -10,11 +10,9 @@ const THREADS: [u64; 3] = [1, 10, 100];
 
 fn fill_btree() -> BTreeMap<u64, u64> {
     let mut btree = BTreeMap::<u64, u64>::new();
-    let mut c = 0;
 
-    for _ in 0..KEYS_IN_STORE {
+    for c in 0..KEYS_IN_STORE {
         btree.insert(c, c);
-        c += 1;
     }
 
     btree
@@ -38,7 +36,7 @@ fn fill_storage(transactions: u64) -> Storage<u64, u64> {
 }
 
 fn write_btree(c: &mut Criterion) {
-    c.bench_function("write_btree", |b| b.iter_with_large_drop(|| fill_btree()));
+    c.bench_function("write_btree", |b| b.iter_with_large_drop(fill_btree));
 }
 
 fn read_btree(c: &mut Criterion) {
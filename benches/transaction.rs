@@ -0,0 +1,191 @@
+use std::collections::{btree_map::Entry, BTreeMap};
+
+use mv::storage::Storage;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const KEYS_IN_STORE: u64 = 100_000;
+const WRITES_IN_TRANSACTION: [u64; 3] = [10, 100, 1_000];
+const HOT_KEYS: u64 = 4;
+
+fn storage() -> Storage<u64, u64> {
+    (0..KEYS_IN_STORE).map(|i| (i, i)).collect()
+}
+
+fn transaction_apply(c: &mut Criterion) {
+    let mut group = c.benchmark_group("transaction_apply");
+    let storage = storage();
+
+    for n in WRITES_IN_TRANSACTION {
+        group.bench_function(BenchmarkId::from_parameter(n), |b| {
+            b.iter(|| {
+                let mut block = storage.block();
+                {
+                    let mut transaction = block.transaction();
+                    // Repeatedly write to the few hot keys
+                    for i in 0..n {
+                        transaction.insert(black_box(i % HOT_KEYS), i);
+                    }
+                    transaction.apply();
+                }
+                block.commit();
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn transaction_abort(c: &mut Criterion) {
+    let mut group = c.benchmark_group("transaction_abort");
+    let storage = storage();
+
+    for n in WRITES_IN_TRANSACTION {
+        group.bench_function(BenchmarkId::from_parameter(n), |b| {
+            b.iter(|| {
+                let mut block = storage.block();
+                {
+                    let mut transaction = block.transaction();
+                    for i in 0..n {
+                        transaction.insert(black_box(i % HOT_KEYS), i);
+                    }
+                }
+                block.commit();
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn many_small_transactions(c: &mut Criterion) {
+    let mut group = c.benchmark_group("many_small_transactions");
+    let storage = storage();
+
+    for n in WRITES_IN_TRANSACTION {
+        group.bench_function(BenchmarkId::from_parameter(n), |b| {
+            b.iter(|| {
+                let mut block = storage.block();
+                // Every transaction writes two keys, every other one is aborted
+                for i in 0..n {
+                    let mut transaction = block.transaction();
+                    transaction.insert(black_box(i), i);
+                    transaction.insert(black_box(i + 1), i);
+                    if i % 2 == 0 {
+                        transaction.apply();
+                    }
+                }
+                block.commit();
+            })
+        });
+    }
+
+    group.finish();
+}
+
+/// Bookkeeping of the block which transaction writes to, without the underlying storage
+#[derive(Default)]
+struct BlockModel {
+    data: BTreeMap<u64, u64>,
+    /// Values before the block and id of the latest transaction which wrote the key
+    prev: BTreeMap<u64, (Option<u64>, u64)>,
+    transactions: u64,
+}
+
+/// Transaction over the block model, applied or aborted
+type TransactionModel = fn(&mut BlockModel, u64, bool);
+
+/// Previous design: transaction keeps revert map which is merged into the block revert map on apply
+fn revert_map_transaction(block: &mut BlockModel, writes: u64, apply: bool) {
+    let mut revert = BTreeMap::new();
+    for i in 0..writes {
+        let key = black_box(i % HOT_KEYS);
+        let prev = block.data.insert(key, i);
+        revert.entry(key).or_insert(prev);
+    }
+    if apply {
+        for (key, value) in revert {
+            block.prev.entry(key).or_insert((value, 0));
+        }
+    } else {
+        for (key, value) in revert {
+            match value {
+                Some(value) => block.data.insert(key, value),
+                None => block.data.remove(&key),
+            };
+        }
+    }
+}
+
+/// Current design: writes are deduplicated against previous values of the block tagged with transaction id
+fn undo_log_transaction(block: &mut BlockModel, writes: u64, apply: bool) {
+    enum Undo {
+        First(u64),
+        Write(u64, Option<u64>),
+    }
+
+    block.transactions += 1;
+    let id = block.transactions;
+    let mut log = Vec::new();
+    for i in 0..writes {
+        let key = black_box(i % HOT_KEYS);
+        let prev = block.data.insert(key, i);
+        match block.prev.entry(key) {
+            Entry::Occupied(mut entry) => {
+                if entry.get().1 != id {
+                    entry.get_mut().1 = id;
+                    log.push(Undo::Write(key, prev));
+                }
+            }
+            Entry::Vacant(entry) => {
+                entry.insert((prev, id));
+                log.push(Undo::First(key));
+            }
+        }
+    }
+    if !apply {
+        for undo in log.into_iter().rev() {
+            let (key, value) = match undo {
+                Undo::First(key) => (key, block.prev.remove(&key).and_then(|(value, _)| value)),
+                Undo::Write(key, value) => (key, value),
+            };
+            match value {
+                Some(value) => block.data.insert(key, value),
+                None => block.data.remove(&key),
+            };
+        }
+    }
+}
+
+fn undo_bookkeeping(c: &mut Criterion) {
+    let mut group = c.benchmark_group("undo_bookkeeping");
+
+    let designs: [(&str, TransactionModel); 2] = [
+        ("revert_map", revert_map_transaction),
+        ("undo_log", undo_log_transaction),
+    ];
+    for (name, transaction) in designs {
+        for n in WRITES_IN_TRANSACTION {
+            group.bench_function(BenchmarkId::new(name, n), |b| {
+                b.iter(|| {
+                    let mut block = BlockModel::default();
+                    // Applied and aborted transactions over the same hot keys
+                    transaction(&mut block, n, true);
+                    transaction(&mut block, n, false);
+                    block
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    transaction_apply,
+    transaction_abort,
+    many_small_transactions,
+    undo_bookkeeping
+);
+criterion_main!(benches);
@@ -0,0 +1,26 @@
+//! Helper to query ranges of composite keys by their prefix
+
+/// Value bounded from both sides, used to query all entries with the same prefix in the ordered map
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Bounded<T> {
+    /// Less than any value
+    Min,
+    /// Actual value
+    Value(T),
+    /// Greater than any value
+    Max,
+}
+
+impl<T> Bounded<T> {
+    /// Get stored value
+    ///
+    /// # Panics
+    ///
+    /// If called on the bound, bounds are only used for queries and never stored.
+    pub(crate) fn value(&self) -> &T {
+        match self {
+            Bounded::Value(value) => value,
+            Bounded::Min | Bounded::Max => unreachable!("bounds are never stored"),
+        }
+    }
+}
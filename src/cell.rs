@@ -1,4 +1,13 @@
-use crate::Value;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use crate::{
+    storage::{NothingToRevert, Redo, RedoError, Reverted},
+    version::Version,
+    Error, Value,
+};
 
 /// Multi-version storage for single value
 pub struct Cell<V: Value> {
@@ -6,21 +15,48 @@ pub struct Cell<V: Value> {
     pub(crate) revert: EbrCell<Option<V>>,
     /// Value which represent aggregated changes of multiple blocks
     pub(crate) blocks: EbrCell<V>,
+    /// Version incremented by every commit, readers retry while commit is published
+    pub(crate) version: Version,
+    /// Value of the latest reverted block, required to redo the revert
+    pub(crate) redo: EbrCell<Redo<Option<V>>>,
+    /// Whether `revert` holds changes of the latest committed block
+    pub(crate) revertible: AtomicBool,
 }
 
+/// Cell which keeps value behind [`Arc`]
+///
+/// Previous version of value in revert shares allocation with the stored value,
+/// so use `make_mut` to clone value only when it's about to be mutated.
+pub type ArcCell<V> = Cell<Arc<V>>;
+
 impl<V: Value> Cell<V> {
     /// Construct new [`Self`]
     pub fn new(v: V) -> Self {
         Self {
             revert: EbrCell::new(None),
             blocks: EbrCell::new(v),
+            version: Version::new(0),
+            redo: EbrCell::new(Redo::default()),
+            revertible: AtomicBool::new(false),
         }
     }
 
+    /// Get amount of blocks committed to the cell
+    pub fn version(&self) -> u64 {
+        self.version.get()
+    }
+
     /// Create persistent view of storage at certain point in time
     pub fn view(&self) -> View<'_, V> {
+        // Retry if commit is published while read transaction is opened
+        self.version.read(|version| self.view_at(version))
+    }
+
+    /// Create view at the version observed by the caller
+    pub(crate) fn view_at(&self, version: u64) -> View<'_, V> {
         View {
             blocks: self.blocks.read(),
+            version,
             _marker: core::marker::PhantomData,
         }
     }
@@ -28,26 +64,97 @@ impl<V: Value> Cell<V> {
     /// Create block to aggregate updates
     pub fn block(&self) -> Block<'_, V> {
         let mut revert = self.revert.write();
+        let mut redo = self.redo.write();
         let blocks = self.blocks.write();
 
         *revert.get_mut() = None;
-
-        Block { revert, blocks }
+        redo.get_mut().changes = None;
+
+        Block {
+            revert,
+            redo,
+            blocks,
+            version: &self.version,
+            reverted: Reverted::Nothing,
+            revertible: &self.revertible,
+            keep_revert: true,
+            carry_redo: false,
+        }
     }
 
     /// Create block to aggregate updates and revert changes made in latest block
+    ///
+    /// Reverted value is kept, so it can be restored with [`Self::block_and_redo`].
+    /// What was reverted is reported by [`Block::reverted`], reverting twice in a row reverts nothing.
+    /// Block which reverts nothing leaves the cell as is and keeps value undone by the previous revert,
+    /// so it can still be redone unless the block writes.
     pub fn block_and_revert(&self) -> Block<'_, V> {
         let mut revert = self.revert.write();
+        let mut redo = self.redo.write();
         let mut blocks = self.blocks.write();
 
-        {
-            let revert = core::mem::take(revert.get_mut());
-            if let Some(revert) = revert {
-                *blocks.get_mut() = revert;
+        let revert_value = core::mem::take(revert.get_mut());
+        let revertible = self.revertible.load(Ordering::Relaxed);
+        let reverted = if revertible {
+            *redo.get_mut() = Redo {
+                version: Some(self.version() + 1),
+                changes: revert_value.map(|revert| core::mem::replace(blocks.get_mut(), revert)),
+            };
+            Reverted::Block {
+                version: self.version(),
+                keys: usize::from(redo.changes.is_some()),
             }
+        } else {
+            // Value left in revert by the previous revert block is not reverted
+            Reverted::Nothing
+        };
+
+        Block {
+            revert,
+            redo,
+            blocks,
+            version: &self.version,
+            reverted,
+            revertible: &self.revertible,
+            keep_revert: false,
+            carry_redo: !revertible,
         }
+    }
 
-        Block { revert, blocks }
+    /// Same as [`Self::block_and_revert`] but fails if there is no committed block to revert
+    pub fn try_block_and_revert(&self) -> Result<Block<'_, V>, NothingToRevert> {
+        let block = self.block_and_revert();
+        match block.reverted {
+            Reverted::Nothing => Err(NothingToRevert),
+            Reverted::Block { .. } => Ok(block),
+        }
+    }
+
+    /// Create block to aggregate updates and restore value undone by the latest revert
+    ///
+    /// Fails if the latest committed block is not a revert.
+    /// Redo survives `serde` snapshots, but not binary snapshots of the value.
+    pub fn block_and_redo(&self) -> Result<Block<'_, V>, RedoError> {
+        let mut revert = self.revert.write();
+        let mut redo = self.redo.write();
+        redo.check(self.version())?;
+        let mut blocks = self.blocks.write();
+
+        // Redo can be reverted again
+        *revert.get_mut() = core::mem::take(redo.get_mut())
+            .changes
+            .map(|next| core::mem::replace(blocks.get_mut(), next));
+
+        Ok(Block {
+            revert,
+            redo,
+            blocks,
+            version: &self.version,
+            reverted: Reverted::Nothing,
+            revertible: &self.revertible,
+            keep_revert: true,
+            carry_redo: false,
+        })
     }
 }
 
@@ -67,6 +174,7 @@ mod view {
     /// Consistent view of the storage at the certain version
     pub struct View<'storage, V: Value> {
         pub(crate) blocks: EbrCellReadTxn<V>,
+        pub(crate) version: u64,
         pub(crate) _marker: core::marker::PhantomData<&'storage V>,
     }
 
@@ -75,6 +183,11 @@ mod view {
         pub fn get(&self) -> &V {
             &self.blocks
         }
+
+        /// Amount of blocks committed to the cell when view was created
+        pub fn version(&self) -> u64 {
+            self.version
+        }
     }
 
     impl<V: Value> Deref for View<'_, V> {
@@ -99,7 +212,15 @@ mod block {
     /// Batched update to the storage that can be reverted later
     pub struct Block<'storage, V: Value> {
         pub(crate) revert: EbrCellWriteTxn<'storage, Option<V>>,
+        pub(crate) redo: EbrCellWriteTxn<'storage, Redo<Option<V>>>,
         pub(crate) blocks: EbrCellWriteTxn<'storage, V>,
+        pub(crate) version: &'storage Version,
+        pub(crate) reverted: Reverted,
+        pub(crate) revertible: &'storage AtomicBool,
+        /// Whether block could be reverted after commit
+        pub(crate) keep_revert: bool,
+        /// Whether block reverted nothing, so redo of the previous revert is kept unless block writes
+        pub(crate) carry_redo: bool,
     }
 
     impl<'storage, V: Value> Block<'storage, V> {
@@ -116,9 +237,38 @@ mod block {
 
         /// Apply aggregated changes to the storage
         pub fn commit(self) {
+            let mut publish = self.version.publish();
+            self.publish(publish.version());
+        }
+
+        /// Same as [`Self::commit`], currently never fails and exists for API symmetry with [`Storage`](crate::storage::Storage) blocks
+        pub fn try_commit(self) -> Result<(), Error> {
+            self.commit();
+            Ok(())
+        }
+
+        /// Publish block while the caller marks the version as being published
+        pub(crate) fn publish(mut self, version: &mut u64) {
+            if self.carry_redo {
+                let written = self.revert.is_some();
+                let redo = self.redo.get_mut();
+                if !written && redo.version == Some(*version) {
+                    redo.version = Some(*version + 1);
+                } else {
+                    redo.changes = None;
+                }
+            }
             // Commit fields in the inverse order
             self.blocks.commit();
+            self.redo.commit();
+            self.revertible.store(self.keep_revert, Ordering::Relaxed);
             self.revert.commit();
+            *version += 1;
+        }
+
+        /// What was reverted when the block was created
+        pub fn reverted(&self) -> Reverted {
+            self.reverted
         }
 
         /// Get mutable access to the value stored in
@@ -134,6 +284,13 @@ mod block {
         }
     }
 
+    impl<V: Value> Block<'_, Arc<V>> {
+        /// Get mutable access to the value stored in, value is cloned only if it's shared
+        pub fn make_mut(&mut self) -> &mut V {
+            Arc::make_mut(self.get_mut())
+        }
+    }
+
     impl<V: Value> Deref for Block<'_, V> {
         type Target = V;
 
@@ -175,6 +332,13 @@ mod block {
         }
     }
 
+    impl<V: Value> Transaction<'_, '_, Arc<V>> {
+        /// Get mutable access to the value stored in cell, value is cloned only if it's shared
+        pub fn make_mut(&mut self) -> &mut V {
+            Arc::make_mut(self.get_mut())
+        }
+    }
+
     impl<'block, 'store: 'block, V: Value> Drop for Transaction<'block, 'store, V> {
         fn drop(&mut self) {
             // revert changes made so fur by current transaction
@@ -304,4 +468,157 @@ mod tests {
         // Revert is visible in the view created after revert was applied
         assert_eq!(view2.get(), &1);
     }
+
+    #[test]
+    fn redo() {
+        let cell = Cell::new(0_u64);
+
+        {
+            let mut block = cell.block();
+            *block.get_mut() = 1;
+            block.commit()
+        }
+        assert_eq!(cell.block_and_redo().err(), Some(RedoError::NotReverted));
+
+        // Revert and redo can be repeated back and forth
+        for _ in 0..2 {
+            cell.block_and_revert().commit();
+            assert_eq!(cell.view().get(), &0);
+            cell.block_and_redo().expect("reverted above").commit();
+            assert_eq!(cell.view().get(), &1);
+        }
+
+        cell.block_and_revert().commit();
+        cell.block().commit();
+        assert_eq!(
+            cell.block_and_redo().err(),
+            Some(RedoError::Outdated {
+                reverted: 6,
+                version: 7
+            })
+        );
+        assert_eq!(cell.view().get(), &0);
+    }
+
+    #[test]
+    fn revert_twice_keeps_redo() {
+        let cell = Cell::new(0_u64);
+        {
+            let mut block = cell.block();
+            *block.get_mut() = 1;
+            block.commit()
+        }
+
+        // Revert of nothing leaves the previous revert redoable
+        cell.block_and_revert().commit();
+        let block = cell.block_and_revert();
+        assert_eq!(block.reverted(), Reverted::Nothing);
+        block.commit();
+        assert_eq!(cell.view().get(), &0);
+        cell.block_and_redo().expect("reverted above").commit();
+        assert_eq!(cell.view().get(), &1);
+
+        // Writes of the revert block are not reverted by the next revert
+        {
+            let mut block = cell.block_and_revert();
+            *block.get_mut() = 5;
+            block.commit()
+        }
+        let block = cell.block_and_revert();
+        assert_eq!(block.reverted(), Reverted::Nothing);
+        block.commit();
+        assert_eq!(cell.view().get(), &5);
+
+        // Revert of nothing which writes discards redo
+        cell.block_and_revert().commit();
+        {
+            let mut block = cell.block_and_revert();
+            *block.get_mut() = 6;
+            block.commit()
+        }
+        assert!(cell.block_and_redo().is_err());
+        assert_eq!(cell.view().get(), &6);
+    }
+
+    #[test]
+    fn revert_outcome() {
+        let cell = Cell::new(0_u64);
+        assert_eq!(cell.block_and_revert().reverted(), Reverted::Nothing);
+
+        {
+            let mut block = cell.block();
+            *block.get_mut() = 1;
+            block.commit()
+        }
+        cell.try_block_and_revert()
+            .expect("block committed above")
+            .commit();
+        assert_eq!(cell.view().get(), &0);
+
+        // Revert can't be reverted
+        assert_eq!(cell.try_block_and_revert().err(), Some(NothingToRevert));
+        assert_eq!(cell.view().get(), &0);
+
+        cell.block().commit();
+        assert_eq!(
+            cell.block_and_revert().reverted(),
+            Reverted::Block {
+                version: 3,
+                keys: 0
+            }
+        );
+    }
+
+    #[test]
+    fn commit_is_atomic() {
+        const BLOCKS: u64 = 1_000;
+
+        let cell = Cell::new(0_u64);
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for i in 1..=BLOCKS {
+                    let mut block = cell.block();
+                    *block.get_mut() = i;
+                    block.commit();
+                }
+            });
+
+            // Block `i` writes `i`, so value must match the version of the view
+            loop {
+                let view = cell.view();
+                assert_eq!(*view.get(), view.version());
+                if view.version() == BLOCKS {
+                    break;
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn arc_make_mut() {
+        let cell = ArcCell::new(Arc::new(vec![0_u64]));
+
+        let view0 = cell.view();
+
+        {
+            let mut block = cell.block();
+            block.make_mut().push(1);
+            {
+                let mut transaction = block.transaction();
+                transaction.make_mut().push(2);
+            }
+            block.commit()
+        }
+
+        let view1 = cell.view();
+
+        cell.block_and_revert().commit();
+
+        let view2 = cell.view();
+
+        assert_eq!(view1.as_slice(), [0, 1]);
+        // Reverted value is the same allocation as before the block
+        assert!(Arc::ptr_eq(&view0, &view2));
+    }
 }
@@ -0,0 +1,457 @@
+//! Streaming binary import and export of storage entries
+//!
+//! Entries are written as a stream of records ordered by key, each record is prefixed by
+//! [`RECORD`] tag and stream is terminated by [`END`] tag, so truncated streams are detected.
+//! Keys and values are encoded with [`Encode`] and decoded with [`Decode`].
+
+use core::fmt;
+use std::io::{self, Read, Write};
+
+use crate::{
+    storage::{Storage, StorageReadOnly, View},
+    Error, Key, Value,
+};
+
+/// Tag which precedes every record in the stream
+pub const RECORD: u8 = 1;
+/// Tag which terminates the stream
+pub const END: u8 = 0;
+
+/// Maximum amount of bytes read at once, so that untrusted length doesn't allocate more than is read
+const READ_STEP: usize = 64 * 1024;
+
+/// Write value in the binary form
+pub trait Encode {
+    /// Write binary representation of `self` into the writer
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()>;
+}
+
+/// Read value from the binary form
+pub trait Decode: Sized {
+    /// Read binary representation of the value from the reader
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self>;
+
+    /// Read `len` values written one after another, used by [`Vec`] so that bytes are read in bulk
+    fn decode_many<R: Read>(reader: &mut R, len: u64) -> io::Result<Vec<Self>> {
+        // Length comes from untrusted input so don't preallocate
+        (0..len).map(|_| Self::decode(reader)).collect()
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        (**self).encode(writer)
+    }
+}
+
+macro_rules! impl_codec_for_int {
+    ($($ty:ty),*) => {$(
+        impl Encode for $ty {
+            fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+                writer.write_all(&self.to_le_bytes())
+            }
+        }
+
+        impl Decode for $ty {
+            fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+                let mut bytes = [0; core::mem::size_of::<$ty>()];
+                reader.read_exact(&mut bytes)?;
+                Ok(<$ty>::from_le_bytes(bytes))
+            }
+        }
+    )*};
+}
+
+impl_codec_for_int!(u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl Encode for u8 {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&[*self])
+    }
+}
+
+impl Decode for u8 {
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    /// Buffer grows by at most [`READ_STEP`] before every read, so untrusted length is never preallocated
+    fn decode_many<R: Read>(reader: &mut R, len: u64) -> io::Result<Vec<Self>> {
+        let mut bytes = Vec::new();
+        let mut remaining = len;
+        while remaining > 0 {
+            let step = usize::try_from(remaining).map_or(READ_STEP, |rest| rest.min(READ_STEP));
+            let start = bytes.len();
+            bytes.resize(start + step, 0);
+            reader.read_exact(&mut bytes[start..])?;
+            remaining -= step as u64;
+        }
+        Ok(bytes)
+    }
+}
+
+impl Encode for bool {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        u8::from(*self).encode(writer)
+    }
+}
+
+impl Decode for bool {
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+        match u8::decode(reader)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(invalid_data(format_args!("invalid bool tag {tag}"))),
+        }
+    }
+}
+
+impl Encode for () {
+    fn encode<W: Write>(&self, _writer: &mut W) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Decode for () {
+    fn decode<R: Read>(_reader: &mut R) -> io::Result<Self> {
+        Ok(())
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match self {
+            None => false.encode(writer),
+            Some(value) => {
+                true.encode(writer)?;
+                value.encode(writer)
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+        if bool::decode(reader)? {
+            T::decode(reader).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        (self.len() as u64).encode(writer)?;
+        self.iter().try_for_each(|item| item.encode(writer))
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let len = u64::decode(reader)?;
+        T::decode_many(reader, len)
+    }
+}
+
+impl Encode for String {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        (self.len() as u64).encode(writer)?;
+        writer.write_all(self.as_bytes())
+    }
+}
+
+impl Decode for String {
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+        String::from_utf8(Vec::<u8>::decode(reader)?).map_err(invalid_data)
+    }
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.0.encode(writer)?;
+        self.1.encode(writer)
+    }
+}
+
+impl<A: Decode, B: Decode> Decode for (A, B) {
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+        Ok((A::decode(reader)?, B::decode(reader)?))
+    }
+}
+
+impl<A: Encode, B: Encode, C: Encode> Encode for (A, B, C) {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.0.encode(writer)?;
+        self.1.encode(writer)?;
+        self.2.encode(writer)
+    }
+}
+
+impl<A: Decode, B: Decode, C: Decode> Decode for (A, B, C) {
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+        Ok((A::decode(reader)?, B::decode(reader)?, C::decode(reader)?))
+    }
+}
+
+impl<A: Encode, B: Encode, C: Encode, D: Encode> Encode for (A, B, C, D) {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.0.encode(writer)?;
+        self.1.encode(writer)?;
+        self.2.encode(writer)?;
+        self.3.encode(writer)
+    }
+}
+
+impl<A: Decode, B: Decode, C: Decode, D: Decode> Decode for (A, B, C, D) {
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+        Ok((
+            A::decode(reader)?,
+            B::decode(reader)?,
+            C::decode(reader)?,
+            D::decode(reader)?,
+        ))
+    }
+}
+
+fn invalid_data(error: impl fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+/// Error which might occur during import of the entries stream
+#[derive(Debug)]
+pub enum ImportError {
+    /// Failed to read or decode the record
+    Io(io::Error),
+    /// Record key is less than key of the previous record
+    Unordered {
+        /// Index of the offending record in the stream
+        record: u64,
+    },
+    /// Record key is equal to key of the previous record
+    Duplicate {
+        /// Index of the offending record in the stream
+        record: u64,
+    },
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "failed to read record: {error}"),
+            Self::Unordered { record } => {
+                write!(f, "record {record} is out of order")
+            }
+            Self::Duplicate { record } => write!(f, "record {record} has duplicate key"),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ImportError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+/// Iterate over records of the stream checking that keys are strictly increasing
+struct SortedRecords<'r, R, K, V> {
+    reader: &'r mut R,
+    prev: Option<K>,
+    record: u64,
+    done: bool,
+    _marker: core::marker::PhantomData<fn() -> V>,
+}
+
+impl<R: Read, K: Key + Decode, V: Decode> SortedRecords<'_, R, K, V> {
+    fn read_record(&mut self) -> Result<Option<(K, V)>, ImportError> {
+        match u8::decode(self.reader)? {
+            END => return Ok(None),
+            RECORD => {}
+            tag => return Err(invalid_data(format_args!("invalid record tag {tag}")).into()),
+        }
+        let key = K::decode(self.reader)?;
+        let value = V::decode(self.reader)?;
+        if let Some(prev) = &self.prev {
+            match prev.cmp(&key) {
+                core::cmp::Ordering::Less => {}
+                core::cmp::Ordering::Equal => {
+                    return Err(ImportError::Duplicate {
+                        record: self.record,
+                    })
+                }
+                core::cmp::Ordering::Greater => {
+                    return Err(ImportError::Unordered {
+                        record: self.record,
+                    })
+                }
+            }
+        }
+        self.prev = Some(key.clone());
+        self.record += 1;
+        Ok(Some((key, value)))
+    }
+}
+
+impl<R: Read, K: Key + Decode, V: Decode> Iterator for SortedRecords<'_, R, K, V> {
+    type Item = Result<(K, V), ImportError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let record = self.read_record().transpose();
+        // Stop after the end of stream or the first error
+        self.done = !matches!(record, Some(Ok(_)));
+        record
+    }
+}
+
+impl<K: Key + Decode, V: Value + Decode> Storage<K, V> {
+    /// Build storage from the stream of records ordered by key written by [`View::export_sorted`]
+    ///
+    /// Records are read one by one, so only the storage itself is kept in memory.
+    /// Records are decoded with many small reads, so pass buffered reader (e.g. [`std::io::BufReader`])
+    /// for files and sockets: reader isn't wrapped here as buffer would consume bytes past the end of the stream.
+    ///
+    /// Records are inserted one at a time within a single write transaction, so import takes
+    /// `O(n log n)` although records are ordered: `concread` keeps nodes of the tree private
+    /// and has no constructor which builds the tree from sorted entries in bulk.
+    pub fn import_sorted<R: Read>(reader: &mut R) -> Result<Self, Error> {
+        let storage = SortedRecords {
+            reader,
+            prev: None,
+            record: 0,
+            done: false,
+            _marker: core::marker::PhantomData,
+        }
+        .collect::<Result<_, ImportError>>()?;
+        Ok(storage)
+    }
+}
+
+impl<K: Key + Encode, V: Value + Encode> View<'_, K, V> {
+    /// Write entries of the view as a stream of records ordered by key
+    pub fn export_sorted<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        for (key, value) in self.iter() {
+            RECORD.encode(writer)?;
+            key.encode(writer)?;
+            value.encode(writer)?;
+        }
+        Ok(END.encode(writer)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::proptest;
+
+    fn export(storage: &Storage<u64, String>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        storage
+            .view()
+            .export_sorted(&mut bytes)
+            .expect("write to vec can't fail");
+        bytes
+    }
+
+    fn record(bytes: &mut Vec<u8>, key: u64, value: &str) {
+        RECORD.encode(bytes).expect("write to vec can't fail");
+        (key, value.to_owned())
+            .encode(bytes)
+            .expect("write to vec can't fail");
+    }
+
+    #[test]
+    fn export_import() {
+        let storage = Storage::<u64, String>::from_iter([(1, "a".to_owned()), (0, "b".to_owned())]);
+
+        let bytes = export(&storage);
+        let imported =
+            Storage::<u64, String>::import_sorted(&mut bytes.as_slice()).expect("valid stream");
+
+        assert!(imported.view().iter().eq(storage.view().iter()));
+    }
+
+    #[test]
+    fn import_errors() {
+        let mut unordered = Vec::new();
+        record(&mut unordered, 1, "a");
+        record(&mut unordered, 0, "b");
+        END.encode(&mut unordered).expect("write to vec can't fail");
+        assert!(matches!(
+            Storage::<u64, String>::import_sorted(&mut unordered.as_slice()),
+            Err(Error::Import(ImportError::Unordered { record: 1 }))
+        ));
+
+        let mut duplicate = Vec::new();
+        record(&mut duplicate, 0, "a");
+        record(&mut duplicate, 1, "b");
+        record(&mut duplicate, 1, "c");
+        END.encode(&mut duplicate).expect("write to vec can't fail");
+        assert!(matches!(
+            Storage::<u64, String>::import_sorted(&mut duplicate.as_slice()),
+            Err(Error::Import(ImportError::Duplicate { record: 2 }))
+        ));
+
+        // Stream without end tag is truncated
+        let storage = Storage::<u64, String>::from_iter([(0, "a".to_owned())]);
+        let bytes = export(&storage);
+        assert!(matches!(
+            Storage::<u64, String>::import_sorted(&mut &bytes[..bytes.len() - 1]),
+            Err(Error::Import(ImportError::Io(error))) if error.kind() == io::ErrorKind::UnexpectedEof
+        ));
+    }
+
+    #[test]
+    fn decode_bytes() {
+        // Payload spans several read steps
+        let value = "a".repeat(2 * READ_STEP + 1);
+        let mut bytes = Vec::new();
+        value.encode(&mut bytes).expect("write to vec can't fail");
+        assert_eq!(
+            String::decode(&mut bytes.as_slice()).expect("valid string"),
+            value
+        );
+        assert_eq!(
+            Vec::<u8>::decode(&mut bytes.as_slice()).expect("valid bytes"),
+            value.as_bytes()
+        );
+
+        // Length from untrusted input is not preallocated
+        let mut bytes = Vec::new();
+        u64::MAX
+            .encode(&mut bytes)
+            .expect("write to vec can't fail");
+        bytes.extend_from_slice(b"abc");
+        assert!(matches!(
+            Vec::<u8>::decode(&mut bytes.as_slice()),
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof
+        ));
+    }
+
+    proptest! {
+        #[test]
+        fn export_import_roundtrip(entries: Vec<(u64, String)>) {
+            let storage = entries.into_iter().collect::<Storage<u64, String>>();
+
+            let bytes = export(&storage);
+            let imported = Storage::<u64, String>::import_sorted(&mut bytes.as_slice())
+                .expect("valid stream");
+
+            assert!(imported.view().iter().eq(storage.view().iter()));
+        }
+    }
+}
@@ -0,0 +1,358 @@
+//! Commit and views spanning several storages
+//!
+//! Blocks of different storages are committed independently, so views created between the
+//! commits observe some storages updated and others not. [`commit`] marks versions of all their storages
+//! as being published before publishing any block and [`view`] retries until no version it observed
+//! changed while views were created, so views are either all before or all after the coordinated commit.
+//!
+//! Neither of them takes locks: storage has at most one block at a time, so commits never publish
+//! the same version concurrently.
+//!
+//! Opening a block waits for the current block of the storage, so writers which open blocks of the same
+//! storages in different order deadlock. [`block`] opens them in the same order regardless of the tuple order,
+//! blocks opened one by one must follow a single order of storages across all writers.
+
+use core::any::Any;
+use std::sync::Arc;
+
+use crate::{
+    cell::{self, Cell},
+    codec::Encode,
+    storage::{self, BlockMeta, Meta, Storage},
+    version::{self, Version},
+    Key, Value,
+};
+
+/// Traits which can't be implemented outside of the crate
+mod sealed {
+    use crate::{storage::Meta, version::Version};
+
+    pub trait Block<'store> {
+        /// Version of the storage block belongs to
+        fn version(&self) -> &'store Version;
+
+        /// Finish work which might panic before any block is published
+        fn prepare(&mut self);
+
+        /// Publish prepared block with metadata while version is marked as being published
+        fn publish(self, version: &mut u64, meta: &Meta);
+    }
+
+    pub trait Table {
+        type View<'a>
+        where
+            Self: 'a;
+
+        type Block<'a>: Block<'a>
+        where
+            Self: 'a;
+
+        /// Version of the storage, it's address identifies the storage while it's borrowed
+        fn version(&self) -> &Version;
+
+        /// Open block of the storage, waits for the current one
+        fn block(&self) -> Self::Block<'_>;
+
+        /// Create view at the observed version
+        fn view_at(&self, version: u64) -> Self::View<'_>;
+    }
+
+    pub trait Blocks {
+        fn commit(self, meta: Meta);
+    }
+
+    pub trait Tables {
+        type Views;
+        type Blocks;
+
+        fn view(self) -> Self::Views;
+
+        fn block(self) -> Self::Blocks;
+    }
+}
+
+/// Block of [`Storage`] or [`Cell`] which can be committed with [`commit`]
+pub trait Block<'store>: sealed::Block<'store> {}
+
+impl<'store, T: sealed::Block<'store>> Block<'store> for T {}
+
+/// [`Storage`] or [`Cell`] which can be viewed with [`view`] and written with [`block`]
+pub trait Table: sealed::Table {}
+
+impl<T: sealed::Table> Table for T {}
+
+/// Tuple of blocks which can be committed with [`commit`]
+pub trait Blocks: sealed::Blocks {}
+
+impl<T: sealed::Blocks> Blocks for T {}
+
+/// Tuple of references to storages which can be viewed with [`view`] and written with [`block`]
+pub trait Tables: sealed::Tables {}
+
+impl<T: sealed::Tables> Tables for T {}
+
+impl<'store, K: Key, V: Value> sealed::Block<'store> for storage::Block<'store, K, V> {
+    fn version(&self) -> &'store Version {
+        self.version
+    }
+
+    fn prepare(&mut self) {
+        storage::Block::prepare(self);
+    }
+
+    fn publish(self, version: &mut u64, meta: &Meta) {
+        storage::Block::publish(self, version, meta.clone());
+    }
+}
+
+impl<'store, V: Value> sealed::Block<'store> for cell::Block<'store, V> {
+    fn version(&self) -> &'store Version {
+        self.version
+    }
+
+    fn prepare(&mut self) {}
+
+    /// Cell doesn't keep metadata
+    fn publish(self, version: &mut u64, _meta: &Meta) {
+        cell::Block::publish(self, version);
+    }
+}
+
+impl<K: Key, V: Value> sealed::Table for Storage<K, V> {
+    type View<'a> = storage::View<'a, K, V>;
+    type Block<'a> = storage::Block<'a, K, V>;
+
+    fn version(&self) -> &Version {
+        &self.version
+    }
+
+    fn block(&self) -> Self::Block<'_> {
+        Storage::block(self)
+    }
+
+    fn view_at(&self, version: u64) -> Self::View<'_> {
+        Storage::view_at(self, version)
+    }
+}
+
+impl<V: Value> sealed::Table for Cell<V> {
+    type View<'a> = cell::View<'a, V>;
+    type Block<'a> = cell::Block<'a, V>;
+
+    fn version(&self) -> &Version {
+        &self.version
+    }
+
+    fn block(&self) -> Self::Block<'_> {
+        Cell::block(self)
+    }
+
+    fn view_at(&self, version: u64) -> Self::View<'_> {
+        Cell::view_at(self, version)
+    }
+}
+
+macro_rules! impl_for_tuples {
+    ($($name:ident $idx:tt),+) => {
+        impl<'store, $($name: Block<'store>),+> sealed::Blocks for ($($name,)+) {
+            fn commit(mut self, meta: Meta) {
+                $(sealed::Block::prepare(&mut self.$idx);)+
+                // Every version is marked before any block is published and released after all of them are
+                let mut publish = ($(sealed::Block::version(&self.$idx).publish(),)+);
+                $(sealed::Block::publish(self.$idx, publish.$idx.version(), &meta);)+
+            }
+        }
+
+        impl<'a, $($name: Table),+> sealed::Tables for ($(&'a $name,)+) {
+            type Views = ($($name::View<'a>,)+);
+            type Blocks = ($($name::Block<'a>,)+);
+
+            fn view(self) -> Self::Views {
+                version::read_all([$(sealed::Table::version(self.$idx)),+], |versions| {
+                    ($(sealed::Table::view_at(self.$idx, versions[$idx]),)+)
+                })
+            }
+
+            fn block(self) -> Self::Blocks {
+                let mut order = [$((core::ptr::from_ref(sealed::Table::version(self.$idx)) as usize, $idx)),+];
+                order.sort_unstable();
+                assert!(
+                    order.windows(2).all(|pair| pair[0].0 != pair[1].0),
+                    "block of the same storage is opened twice"
+                );
+                let mut blocks = ($(None::<$name::Block<'a>>,)+);
+                for (_, idx) in order {
+                    match idx {
+                        $($idx => blocks.$idx = Some(sealed::Table::block(self.$idx)),)+
+                        _ => unreachable!("index of the tuple element"),
+                    }
+                }
+                ($(blocks.$idx.expect("every block is opened"),)+)
+            }
+        }
+    };
+}
+
+impl_for_tuples!(A 0);
+impl_for_tuples!(A 0, B 1);
+impl_for_tuples!(A 0, B 1, C 2);
+impl_for_tuples!(A 0, B 1, C 2, D 3);
+impl_for_tuples!(A 0, B 1, C 2, D 3, E 4);
+impl_for_tuples!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_for_tuples!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_for_tuples!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+/// Open blocks of the tuple of storages to be committed with [`commit`]
+///
+/// Blocks are opened in the order of storages which doesn't depend on the order in the tuple,
+/// so concurrent writers opening blocks of the same storages don't deadlock.
+///
+/// # Panics
+///
+/// If the same storage appears in the tuple twice, as it's second block would wait for the first one forever.
+pub fn block<T: Tables>(tables: T) -> T::Blocks {
+    sealed::Tables::block(tables)
+}
+
+/// Commit tuple of blocks of different storages, so that they are published at once
+///
+/// All blocks are prepared before any of them is published, so panic during preparation
+/// (e.g. in index extractor) leaves every storage unchanged.
+pub fn commit(blocks: impl Blocks) {
+    sealed::Blocks::commit(blocks, None);
+}
+
+/// Same as [`commit`] but attaches metadata to the resulting version of every [`Storage`] in the tuple
+///
+/// See [`storage::Block::commit_with`] for details, [`Cell`] doesn't keep metadata.
+pub fn commit_with<M: Any + Send + Sync + Encode>(blocks: impl Blocks, meta: M) {
+    sealed::Blocks::commit(blocks, Some(Arc::new(BlockMeta::new(meta))));
+}
+
+/// Create views of the tuple of storages which are guaranteed to be from the same coordinated commit
+pub fn view<T: Tables>(tables: T) -> T::Views {
+    sealed::Tables::view(tables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::storage::StorageReadOnly;
+
+    #[test]
+    fn commit_and_view() {
+        let accounts = Storage::<u64, u64>::from_iter([(0, 100), (1, 0)]);
+        let total = Cell::new(100_u64);
+
+        {
+            let mut block = accounts.block();
+            block.insert(0, 50);
+            block.insert(1, 70);
+            let mut total_block = total.block();
+            *total_block.get_mut() = 120;
+            commit((block, total_block));
+        }
+
+        let (accounts_view, total_view) = view((&accounts, &total));
+        assert_eq!(accounts_view.get(&1), Some(&70));
+        assert_eq!(total_view.get(), &120);
+        assert_eq!(accounts_view.version(), 1);
+        assert_eq!(total_view.version(), 1);
+
+        // Metadata is attached to every storage
+        let other = Storage::<u64, u64>::new();
+        commit_with((accounts.block(), total.block(), other.block()), 7_u64);
+        let (accounts_view, other_view) = view((&accounts, &other));
+        assert_eq!(accounts_view.meta::<u64>(), Some(&7));
+        assert_eq!(other_view.meta::<u64>(), Some(&7));
+
+        // Same storage can be viewed twice
+        let (view1, view2) = view((&accounts, &accounts));
+        assert_eq!(view1.version(), view2.version());
+    }
+
+    #[test]
+    fn views_are_consistent() {
+        const BLOCKS: u64 = 1_000;
+
+        let storage = Storage::<u64, u64>::from_iter([(0, 0)]);
+        let cell = Cell::new(0_u64);
+        let other = Cell::new(0_u64);
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for i in 1..=BLOCKS {
+                    let (mut block, mut cell_block) = block((&storage, &cell));
+                    block.insert(0, i);
+                    *cell_block.get_mut() = i;
+                    // Blocks are already opened, so they can be committed in any order
+                    if i % 2 == 0 {
+                        commit((block, cell_block));
+                    } else {
+                        commit((cell_block, block));
+                    }
+                }
+            });
+
+            s.spawn(|| {
+                // Commits of unrelated storage interleave with the coordinated ones
+                for i in 1..=BLOCKS {
+                    let mut block = other.block();
+                    *block.get_mut() = i;
+                    commit((block,));
+                }
+            });
+
+            loop {
+                let (cell_view, storage_view, _) = view((&cell, &storage, &other));
+                let value = *storage_view.get(&0).expect("key is present");
+                assert_eq!(cell_view.get(), &value);
+                if value == BLOCKS {
+                    break;
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn blocks_opened_in_any_order() {
+        const BLOCKS: u64 = 1_000;
+
+        let storage = Storage::<u64, u64>::from_iter([(0, 0)]);
+        let cell = Cell::new(0_u64);
+
+        // Writers list storages in opposite order, but blocks are opened in the same one
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for _ in 0..BLOCKS {
+                    let (mut block, mut cell_block) = block((&storage, &cell));
+                    let value = block.get(&0).copied().expect("key is present");
+                    block.insert(0, value + 1);
+                    *cell_block.get_mut() += 1;
+                    commit((block, cell_block));
+                }
+            });
+            s.spawn(|| {
+                for _ in 0..BLOCKS {
+                    let (mut cell_block, mut block) = block((&cell, &storage));
+                    let value = block.get(&0).copied().expect("key is present");
+                    block.insert(0, value + 1);
+                    *cell_block.get_mut() += 1;
+                    commit((cell_block, block));
+                }
+            });
+        });
+
+        let (storage_view, cell_view) = view((&storage, &cell));
+        assert_eq!(storage_view.get(&0), Some(&(2 * BLOCKS)));
+        assert_eq!(cell_view.get(), &(2 * BLOCKS));
+    }
+
+    #[test]
+    #[should_panic(expected = "block of the same storage is opened twice")]
+    fn same_storage_blocked_twice() {
+        let cell = Cell::new(0_u64);
+        let _blocks = block((&cell, &cell));
+    }
+}
@@ -0,0 +1,89 @@
+//! Error type shared by fallible operations of the crate
+
+use core::fmt;
+use std::io;
+
+use crate::{
+    codec::ImportError,
+    snapshot::SnapshotError,
+    storage::{NothingToRevert, QuotaExceeded, RedoError},
+};
+
+/// Error which might occur during operations on storages
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to read or write data
+    Io(io::Error),
+    /// Failed to import stream of entries
+    Import(ImportError),
+    /// Failed to read snapshot
+    Snapshot(SnapshotError),
+    /// Failed to redo reverted block
+    Redo(RedoError),
+    /// There is no committed block to revert
+    NothingToRevert(NothingToRevert),
+    /// Insert would exceed capacity quota of the storage
+    Quota(QuotaExceeded),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "{error}"),
+            Self::Import(error) => write!(f, "{error}"),
+            Self::Snapshot(error) => write!(f, "{error}"),
+            Self::Redo(error) => write!(f, "{error}"),
+            Self::NothingToRevert(error) => write!(f, "{error}"),
+            Self::Quota(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            Self::Import(error) => Some(error),
+            Self::Snapshot(error) => Some(error),
+            Self::Redo(error) => Some(error),
+            Self::NothingToRevert(error) => Some(error),
+            Self::Quota(error) => Some(error),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<ImportError> for Error {
+    fn from(error: ImportError) -> Self {
+        Self::Import(error)
+    }
+}
+
+impl From<SnapshotError> for Error {
+    fn from(error: SnapshotError) -> Self {
+        Self::Snapshot(error)
+    }
+}
+
+impl From<RedoError> for Error {
+    fn from(error: RedoError) -> Self {
+        Self::Redo(error)
+    }
+}
+
+impl From<QuotaExceeded> for Error {
+    fn from(error: QuotaExceeded) -> Self {
+        Self::Quota(error)
+    }
+}
+
+impl From<NothingToRevert> for Error {
+    fn from(error: NothingToRevert) -> Self {
+        Self::NothingToRevert(error)
+    }
+}
@@ -0,0 +1,514 @@
+//! Multi-version key value storage for hashable keys
+
+use std::{borrow::Borrow, collections::HashMap as StdHashMap, hash::Hash};
+
+use concread::{
+    ebrcell::{EbrCell, EbrCellWriteTxn},
+    hashmap::{HashMap, HashMapReadTxn, HashMapWriteTxn},
+};
+
+use crate::{version::Version, HashKey, Value};
+
+/// Multi-version key value storage built on top of hash map
+///
+/// Unlike [`Storage`](crate::storage::Storage) keys are not required to be ordered,
+/// so iteration order is unspecified and range queries are not supported.
+pub struct HashStorage<K: HashKey, V: Value> {
+    /// Previous version of values in the `blocks` map, required to perform revert of the latest changes
+    pub(crate) revert: EbrCell<StdHashMap<K, Option<V>>>,
+    /// Map which represent aggregated changes of multiple blocks
+    pub(crate) blocks: HashMap<K, V>,
+    /// Amount of committed blocks, readers retry while commit is published
+    pub(crate) version: Version,
+}
+
+impl<K: HashKey, V: Value> HashStorage<K, V> {
+    /// Construct new [`Self`]
+    pub fn new() -> Self {
+        Self {
+            revert: EbrCell::new(StdHashMap::new()),
+            blocks: HashMap::new(),
+            version: Version::new(0),
+        }
+    }
+
+    /// Get amount of blocks committed to the storage
+    pub fn version(&self) -> u64 {
+        self.version.get()
+    }
+
+    /// Create persistent view of storage at certain point in time
+    pub fn view(&self) -> View<'_, K, V> {
+        // Retry if commit is published while read transaction is opened
+        self.version.read(|version| View {
+            blocks: self.blocks.read(),
+            version,
+        })
+    }
+
+    /// Create block to aggregate updates
+    pub fn block(&self) -> Block<'_, K, V> {
+        let mut revert = self.revert.write();
+        let blocks = self.blocks.write();
+
+        // Clear revert
+        revert.get_mut().clear();
+
+        Block {
+            revert,
+            blocks,
+            version: &self.version,
+        }
+    }
+
+    /// Create block to aggregate updates and revert changes created in the latest block
+    pub fn block_and_revert(&self) -> Block<'_, K, V> {
+        let mut revert = self.revert.write();
+        let mut blocks = self.blocks.write();
+
+        {
+            let revert = core::mem::take(revert.get_mut());
+            for (key, value) in revert {
+                match value {
+                    None => blocks.remove(&key),
+                    Some(value) => blocks.insert(key, value),
+                };
+            }
+        }
+
+        Block {
+            revert,
+            blocks,
+            version: &self.version,
+        }
+    }
+}
+
+impl<K: HashKey, V: Value> Default for HashStorage<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: HashKey, V: Value> FromIterator<(K, V)> for HashStorage<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Self {
+            revert: EbrCell::new(StdHashMap::new()),
+            blocks: iter.into_iter().collect(),
+            version: Version::new(0),
+        }
+    }
+}
+
+pub trait HashStorageReadOnly<K: HashKey, V: Value> {
+    /// Read entry from the storage
+    fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized;
+
+    /// Iterate over all entries in the storage in unspecified order
+    fn iter(&self) -> Iter<'_, K, V>;
+
+    /// Get amount of entries in the storage
+    fn len(&self) -> usize;
+
+    /// Check if storage has no entries
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Module for [`View`] and it's related impls
+mod view {
+    use super::*;
+    /// Consistent view of the storage at the certain version
+    pub struct View<'storage, K: HashKey, V: Value> {
+        pub(crate) blocks: HashMapReadTxn<'storage, K, V>,
+        pub(crate) version: u64,
+    }
+
+    impl<K: HashKey, V: Value> View<'_, K, V> {
+        /// Amount of blocks committed to the storage when view was created
+        pub fn version(&self) -> u64 {
+            self.version
+        }
+    }
+
+    impl<K: HashKey, V: Value> HashStorageReadOnly<K, V> for View<'_, K, V> {
+        fn get<Q>(&self, key: &Q) -> Option<&V>
+        where
+            K: Borrow<Q>,
+            Q: Hash + Eq + ?Sized,
+        {
+            self.blocks.get(key)
+        }
+
+        fn iter(&self) -> Iter<'_, K, V> {
+            Iter {
+                iter: self.blocks.iter(),
+            }
+        }
+
+        fn len(&self) -> usize {
+            self.blocks.len()
+        }
+    }
+}
+pub use view::View;
+
+/// Module for [`Block`] and it's related impls
+mod block {
+    use super::*;
+
+    /// Batched update to the storage that can be reverted later
+    pub struct Block<'store, K: HashKey, V: Value> {
+        pub(crate) revert: EbrCellWriteTxn<'store, StdHashMap<K, Option<V>>>,
+        pub(crate) blocks: HashMapWriteTxn<'store, K, V>,
+        pub(crate) version: &'store Version,
+    }
+
+    impl<'store, K: HashKey, V: Value> Block<'store, K, V> {
+        /// Create transaction for the block
+        pub fn transaction<'block>(&'block mut self) -> Transaction<'block, 'store, K, V>
+        where
+            'store: 'block,
+        {
+            Transaction {
+                block: self,
+                revert: StdHashMap::new(),
+            }
+        }
+
+        /// Apply aggregated changes to the storage
+        pub fn commit(self) {
+            let mut publish = self.version.publish();
+            // Commit fields in the inverse order
+            self.blocks.commit();
+            self.revert.commit();
+            *publish.version() += 1;
+        }
+
+        /// Get mutable access to the value stored in
+        pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+            self.blocks.get_mut(key).inspect(|value| {
+                self.revert
+                    .entry(key.clone())
+                    .or_insert_with(|| Some((*value).clone()));
+            })
+        }
+
+        /// Insert key value into the storage
+        pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+            let prev_value = self.blocks.insert(key.clone(), value);
+            self.revert.entry(key).or_insert_with(|| prev_value.clone());
+            prev_value
+        }
+
+        /// Remove key value from storage
+        pub fn remove(&mut self, key: K) -> Option<V> {
+            let prev_value = self.blocks.remove(&key);
+            self.revert.entry(key).or_insert_with(|| prev_value.clone());
+            prev_value
+        }
+    }
+
+    impl<K: HashKey, V: Value> HashStorageReadOnly<K, V> for Block<'_, K, V> {
+        fn get<Q>(&self, key: &Q) -> Option<&V>
+        where
+            K: Borrow<Q>,
+            Q: Hash + Eq + ?Sized,
+        {
+            self.blocks.get(key)
+        }
+
+        fn iter(&self) -> Iter<'_, K, V> {
+            Iter {
+                iter: self.blocks.iter(),
+            }
+        }
+
+        fn len(&self) -> usize {
+            self.blocks.len()
+        }
+    }
+
+    /// Part of block's aggregated changes which applied or aborted at the same time
+    pub struct Transaction<'block, 'store, K: HashKey, V: Value> {
+        pub(crate) revert: StdHashMap<K, Option<V>>,
+        pub(crate) block: &'block mut Block<'store, K, V>,
+    }
+
+    impl<'block, 'store: 'block, K: HashKey, V: Value> Transaction<'block, 'store, K, V> {
+        /// Apply aggregated changes of [`Transaction`] to the [`Block`]
+        pub fn apply(mut self) {
+            for (key, value) in core::mem::take(&mut self.revert) {
+                self.block.revert.entry(key).or_insert(value);
+            }
+        }
+
+        /// Get mutable access to the value stored in
+        pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+            self.block.blocks.get_mut(key).inspect(|value| {
+                self.revert
+                    .entry(key.clone())
+                    .or_insert_with(|| Some((*value).clone()));
+            })
+        }
+
+        /// Insert key value into the transaction temporary map
+        pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+            let prev_value = self.block.blocks.insert(key.clone(), value);
+            self.revert.entry(key).or_insert_with(|| prev_value.clone());
+            prev_value
+        }
+
+        /// Remove key value from storage
+        pub fn remove(&mut self, key: K) -> Option<V> {
+            let prev_value = self.block.blocks.remove(&key);
+            self.revert.entry(key).or_insert_with(|| prev_value.clone());
+            prev_value
+        }
+    }
+
+    impl<K: HashKey, V: Value> HashStorageReadOnly<K, V> for Transaction<'_, '_, K, V> {
+        fn get<Q>(&self, key: &Q) -> Option<&V>
+        where
+            K: Borrow<Q>,
+            Q: Hash + Eq + ?Sized,
+        {
+            self.block.get(key)
+        }
+
+        fn iter(&self) -> Iter<'_, K, V> {
+            self.block.iter()
+        }
+
+        fn len(&self) -> usize {
+            self.block.len()
+        }
+    }
+
+    impl<'block, 'store: 'block, K: HashKey, V: Value> Drop for Transaction<'block, 'store, K, V> {
+        fn drop(&mut self) {
+            // revert changes made so far by current transaction
+            // if transaction was applied set would be empty
+            for (key, value) in core::mem::take(&mut self.revert) {
+                match value {
+                    None => self.block.blocks.remove(&key),
+                    Some(value) => self.block.blocks.insert(key, value),
+                };
+            }
+        }
+    }
+}
+pub use block::{Block, Transaction};
+
+mod iter {
+    use super::*;
+
+    /// Iterate over entries in block, view or transaction
+    pub struct Iter<'slf, K: HashKey, V: Value> {
+        pub(crate) iter: concread::internals::hashmap::iter::Iter<'slf, K, V>,
+    }
+
+    impl<'slf, K: HashKey, V: Value> Iterator for Iter<'slf, K, V> {
+        type Item = (&'slf K, &'slf V);
+
+        fn next(&mut self) -> Option<Self::Item> {
+            self.iter.next()
+        }
+    }
+}
+pub use iter::Iter;
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use super::*;
+
+    use proptest::proptest;
+
+    #[test]
+    fn get() {
+        let storage = HashStorage::<String, u64>::new();
+
+        let view0 = storage.view();
+
+        {
+            let mut block = storage.block();
+            for (key, value) in [("a", 0), ("b", 0)] {
+                block.insert(key.to_owned(), value);
+            }
+            block.commit()
+        }
+
+        let view1 = storage.view();
+
+        {
+            let mut block = storage.block();
+            block.insert("a".to_owned(), 1);
+            block.remove("b".to_owned());
+            block.commit()
+        }
+
+        let view2 = storage.view();
+
+        assert_eq!(view0.get("a"), None);
+        assert_eq!(view1.get("a"), Some(&0));
+        assert_eq!(view1.get("b"), Some(&0));
+        assert_eq!(view2.get("a"), Some(&1));
+        assert_eq!(view2.get("b"), None);
+        assert_eq!(view2.len(), 1);
+    }
+
+    #[test]
+    fn transaction_step() {
+        let storage = HashStorage::<u64, u64>::new();
+
+        let mut block = storage.block();
+
+        // Successful transaction
+        {
+            let mut transaction = block.transaction();
+            transaction.insert(0, 0);
+            transaction.apply();
+        }
+
+        // Aborted step
+        {
+            let mut transaction = block.transaction();
+            transaction.insert(0, 1);
+            transaction.insert(1, 1);
+        }
+
+        // Check that aborted transaction changes don't visible for subsequent transactions
+        {
+            let transaction = block.transaction();
+            assert_eq!(transaction.get(&0), Some(&0));
+            assert_eq!(transaction.get(&1), None);
+        }
+
+        block.commit();
+
+        let view = storage.view();
+        assert_eq!(view.get(&0), Some(&0));
+        assert_eq!(view.get(&1), None);
+    }
+
+    #[test]
+    fn revert() {
+        let storage = HashStorage::<u64, u64>::new();
+
+        {
+            let mut block = storage.block();
+            block.insert(0, 0);
+            block.commit()
+        }
+
+        {
+            let mut block = storage.block();
+            *block.get_mut(&0).expect("inserted above") = 1;
+            block.insert(1, 1);
+            block.commit()
+        }
+
+        let view1 = storage.view();
+
+        storage.block_and_revert().commit();
+
+        let view2 = storage.view();
+
+        // View is persistent so revert is not visible
+        assert_eq!(view1.get(&0), Some(&1));
+        assert_eq!(view1.get(&1), Some(&1));
+        // Revert is visible in the view created after revert was applied
+        assert_eq!(view2.get(&0), Some(&0));
+        assert_eq!(view2.get(&1), None);
+    }
+
+    #[test]
+    fn commit_is_atomic() {
+        const BLOCKS: u64 = 1_000;
+        const KEYS: u64 = 4;
+
+        let storage = (0..KEYS)
+            .map(|key| (key, 0))
+            .collect::<HashStorage<u64, u64>>();
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for i in 1..=BLOCKS {
+                    let mut block = storage.block();
+                    for key in 0..KEYS {
+                        block.insert(key, i);
+                    }
+                    block.commit();
+                }
+            });
+
+            // Block `i` writes `i` to every key, so values and revert must match the version
+            loop {
+                let view = storage.view();
+                let version = view.version();
+                assert!(view.iter().all(|(_, value)| *value == version));
+
+                {
+                    let (revert, blocks) = storage
+                        .version
+                        .read(|_| (storage.revert.read(), storage.blocks.read()));
+                    let value = *blocks.get(&0).expect("key is present");
+                    assert!(blocks.iter().all(|(_, v)| *v == value));
+                    assert!(revert.values().all(|prev| *prev == Some(value - 1)));
+                }
+
+                if version == BLOCKS {
+                    break;
+                }
+            }
+        });
+    }
+
+    proptest! {
+        #[test]
+        fn consistent_with_btreemap(txs: Vec<(bool, Vec<(u64, Option<u64>)>)>) {
+            let storage = HashStorage::<u64, u64>::new();
+            let mut map = BTreeMap::new();
+            let mut keys = BTreeSet::new();
+
+            for (committed, tx) in txs {
+                let mut block = storage.block();
+
+                for (key, value) in tx {
+                    keys.insert(key);
+                    match value {
+                        Some(value) => {
+                            if committed {
+                                map.insert(key, value);
+                            }
+                            block.insert(key, value);
+                        }
+                        None => {
+                            if committed {
+                                map.remove(&key);
+                            }
+                            block.remove(key);
+                        }
+                    }
+                }
+
+                if committed {
+                    block.commit()
+                }
+            }
+
+            let view = storage.view();
+
+            for key in keys.iter() {
+                assert_eq!(view.get(key), map.get(key));
+            }
+            assert_eq!(view.iter().map(|(k, v)| (*k, *v)).collect::<BTreeMap<_, _>>(), map);
+        }
+    }
+}
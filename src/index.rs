@@ -0,0 +1,439 @@
+//! Secondary indexes maintained alongside [`Storage`](crate::storage::Storage)
+//!
+//! Index is declared with [`Storage::add_index`](crate::storage::Storage::add_index) from the function
+//! which extracts indexed value from the storage value.
+//! It's updated by every write to the block or transaction, rolled back together with them and
+//! can be queried from [`View::index`](crate::storage::View::index).
+
+use core::{
+    any::Any,
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+};
+use std::{
+    collections::BTreeSet,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use concread::bptree::{BptreeMap, BptreeMapReadTxn, BptreeMapWriteTxn};
+
+use crate::{bounded::Bounded, Key, Value};
+
+/// Entry of the index: indexed value and key of the entry in the storage
+type Entry<I, K> = (I, Bounded<K>);
+
+/// Bounds of the index entries range
+type EntryRange<I, K> = (Bound<Entry<I, K>>, Bound<Entry<I, K>>);
+
+/// Function which extracts indexed value from the storage value
+pub type Extractor<I, V> = dyn Fn(&V) -> I + Send + Sync;
+
+/// Marker which keeps index handle `Send`, `Sync` and `Copy` regardless of parameters
+pub(crate) type Marker<I, K, V> = PhantomData<fn() -> (I, K, V)>;
+
+/// Source of ids which are unique across all indexes of all storages
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Handle to the secondary index of the storage
+pub struct Index<I, K, V> {
+    /// Position of the index in the storage
+    pub(crate) position: usize,
+    /// Id of the index, used to detect handles of another storage
+    pub(crate) id: u64,
+    pub(crate) _marker: Marker<I, K, V>,
+}
+
+impl<I, K, V> Clone for Index<I, K, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<I, K, V> Copy for Index<I, K, V> {}
+
+/// Index entries stored in the storage
+pub(crate) struct IndexData<I: Key, K: Key, V: Value> {
+    id: u64,
+    map: BptreeMap<Entry<I, K>, ()>,
+    extractor: Box<Extractor<I, V>>,
+}
+
+impl<I: Key, K: Key, V: Value> IndexData<I, K, V> {
+    /// Build index from the existing storage entries
+    pub(crate) fn new<'a>(
+        extractor: Box<Extractor<I, V>>,
+        entries: impl Iterator<Item = (&'a K, &'a V)>,
+    ) -> Self {
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            map: entries
+                .map(|(key, value)| ((extractor(value), Bounded::Value(key.clone())), ()))
+                .collect(),
+            extractor,
+        }
+    }
+}
+
+/// Type erased index stored in the storage
+pub(crate) trait ErasedIndex<K, V>: Send + Sync {
+    /// Id of the index unique across all storages
+    fn id(&self) -> u64;
+
+    /// Create persistent view of the index
+    fn read(&self) -> Box<dyn ErasedIndexRead<K> + '_>;
+
+    /// Create write transaction of the index
+    fn write(&self) -> Box<dyn ErasedIndexWrite<K, V> + '_>;
+}
+
+/// Iterator over type erased index entries
+type ErasedIter<'slf, K> = Box<dyn Iterator<Item = (&'slf dyn Any, &'slf K)> + 'slf>;
+
+/// Type erased view of the index
+pub(crate) trait ErasedIndexRead<K>: Send + Sync {
+    /// Id of the index unique across all storages
+    fn id(&self) -> u64;
+
+    /// Iterate over entries with indexed value in bounds, bounds must be of the index value type
+    fn range(&self, bounds: (Bound<&dyn Any>, Bound<&dyn Any>)) -> ErasedIter<'_, K>;
+}
+
+/// Type erased write transaction of the index
+pub(crate) trait ErasedIndexWrite<K, V> {
+    /// Replace index entry for the `old` value of the key with entry for the `new` value
+    fn update(&mut self, key: &K, old: Option<&V>, new: Option<&V>);
+
+    /// Apply changes to the index
+    fn commit(self: Box<Self>);
+}
+
+impl<I: Key, K: Key, V: Value> ErasedIndex<K, V> for IndexData<I, K, V> {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn read(&self) -> Box<dyn ErasedIndexRead<K> + '_> {
+        Box::new(IndexRead {
+            id: self.id,
+            map: self.map.read(),
+        })
+    }
+
+    fn write(&self) -> Box<dyn ErasedIndexWrite<K, V> + '_> {
+        Box::new(IndexWrite {
+            map: self.map.write(),
+            extractor: &*self.extractor,
+        })
+    }
+}
+
+struct IndexRead<'index, I: Key, K: Key> {
+    id: u64,
+    map: BptreeMapReadTxn<'index, Entry<I, K>, ()>,
+}
+
+impl<I: Key, K: Key> ErasedIndexRead<K> for IndexRead<'_, I, K> {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn range(&self, bounds: (Bound<&dyn Any>, Bound<&dyn Any>)) -> ErasedIter<'_, K> {
+        Box::new(
+            self.map
+                .range::<_, Entry<I, K>>(entry_range::<I, K>(bounds))
+                .map(|((value, key), ())| (value as &dyn Any, key.value())),
+        )
+    }
+}
+
+struct IndexWrite<'index, I: Key, K: Key, V: Value> {
+    map: BptreeMapWriteTxn<'index, Entry<I, K>, ()>,
+    extractor: &'index Extractor<I, V>,
+}
+
+impl<I: Key, K: Key, V: Value> ErasedIndexWrite<K, V> for IndexWrite<'_, I, K, V> {
+    fn update(&mut self, key: &K, old: Option<&V>, new: Option<&V>) {
+        let old = old.map(self.extractor);
+        let new = new.map(self.extractor);
+        if old == new {
+            return;
+        }
+        if let Some(old) = old {
+            self.map.remove(&(old, Bounded::Value(key.clone())));
+        }
+        if let Some(new) = new {
+            self.map.insert((new, Bounded::Value(key.clone())), ());
+        }
+    }
+
+    fn commit(self: Box<Self>) {
+        self.map.commit();
+    }
+}
+
+/// Convert bounds of the indexed value into bounds of index entries
+fn entry_range<I: Key, K: Key>(bounds: (Bound<&dyn Any>, Bound<&dyn Any>)) -> EntryRange<I, K> {
+    let downcast = |value: &dyn Any| {
+        value
+            .downcast_ref::<I>()
+            .expect("index handle belongs to another storage")
+            .clone()
+    };
+    let start = match bounds.0 {
+        Bound::Included(value) => Bound::Included((downcast(value), Bounded::Min)),
+        Bound::Excluded(value) => Bound::Excluded((downcast(value), Bounded::Max)),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    let end = match bounds.1 {
+        Bound::Included(value) => Bound::Included((downcast(value), Bounded::Max)),
+        Bound::Excluded(value) => Bound::Excluded((downcast(value), Bounded::Min)),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    (start, end)
+}
+
+/// Write transactions of all storage indexes held by the block
+pub(crate) struct Indexes<'store, K: Key, V: Value> {
+    writes: Vec<Box<dyn ErasedIndexWrite<K, V> + 'store>>,
+    /// Keys which are mutably borrowed and whose index entries must be recomputed
+    dirty: BTreeSet<K>,
+}
+
+impl<'store, K: Key, V: Value> Indexes<'store, K, V> {
+    pub(crate) fn write(indexes: &'store [Box<dyn ErasedIndex<K, V>>]) -> Self {
+        Self {
+            writes: indexes.iter().map(|index| index.write()).collect(),
+            dirty: BTreeSet::new(),
+        }
+    }
+
+    /// Check if storage has no indexes
+    pub(crate) fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Replace index entries of the key, `old` is ignored if key is dirty
+    pub(crate) fn update(&mut self, key: &K, old: Option<&V>, new: Option<&V>) {
+        let old = if self.dirty.remove(key) { None } else { old };
+        for index in &mut self.writes {
+            index.update(key, old, new);
+        }
+    }
+
+    /// Remove index entries of the key which value is about to be mutated
+    pub(crate) fn mark_dirty(&mut self, key: &K, value: &V) {
+        if !self.dirty.contains(key) {
+            for index in &mut self.writes {
+                index.update(key, Some(value), None);
+            }
+            self.dirty.insert(key.clone());
+        }
+    }
+
+    /// Take keys whose index entries must be recomputed
+    pub(crate) fn take_dirty(&mut self) -> BTreeSet<K> {
+        core::mem::take(&mut self.dirty)
+    }
+
+    /// Apply changes to all indexes
+    pub(crate) fn commit(self) {
+        debug_assert!(
+            self.dirty.is_empty(),
+            "dirty keys must be flushed before commit"
+        );
+        for index in self.writes {
+            index.commit();
+        }
+    }
+}
+
+/// Consistent view of the index at the version of storage view it's obtained from
+pub struct IndexView<'view, I, K> {
+    pub(crate) index: &'view dyn ErasedIndexRead<K>,
+    pub(crate) _marker: PhantomData<fn() -> I>,
+}
+
+impl<'view, I: Key, K: Key> IndexView<'view, I, K> {
+    /// Iterate over indexed values in range and keys of corresponding storage entries
+    ///
+    /// Entries are ordered by indexed value and then by key.
+    pub fn range(&self, bounds: impl RangeBounds<I>) -> IndexIter<'view, I, K> {
+        let bounds = (
+            bounds.start_bound().map(|value| value as &dyn Any),
+            bounds.end_bound().map(|value| value as &dyn Any),
+        );
+        IndexIter {
+            iter: self.index.range(bounds),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Iterate over keys of storage entries with the indexed value
+    pub fn get(&self, value: &I) -> impl Iterator<Item = &'view K> {
+        self.range((Bound::Included(value), Bound::Included(value)))
+            .map(|(_, key)| key)
+    }
+
+    /// Iterate over all index entries
+    pub fn iter(&self) -> IndexIter<'view, I, K> {
+        self.range(..)
+    }
+}
+
+/// Iterate over entries of the index
+pub struct IndexIter<'view, I, K> {
+    iter: ErasedIter<'view, K>,
+    _marker: PhantomData<fn() -> I>,
+}
+
+impl<'view, I: Key, K: Key> Iterator for IndexIter<'view, I, K> {
+    type Item = (&'view I, &'view K);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|(value, key)| {
+            (
+                value
+                    .downcast_ref::<I>()
+                    .expect("index entries has indexed value type"),
+                key,
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::storage::Storage;
+
+    use proptest::proptest;
+
+    #[test]
+    fn maintained_by_block() {
+        let mut storage = Storage::<u64, u64>::from_iter([(0, 10), (1, 11)]);
+        let by_parity = storage.add_index(|value: &u64| value.is_multiple_of(2));
+
+        {
+            let mut block = storage.block();
+            block.insert(2, 12);
+            block.remove(1);
+            *block.get_mut(&0).expect("inserted above") = 13;
+            block.commit();
+        }
+
+        let view = storage.view();
+        let index = view.index(&by_parity);
+        assert!(index.get(&true).copied().eq([2]));
+        assert!(index.get(&false).copied().eq([0]));
+        assert!(index.iter().eq([(&false, &0), (&true, &2)]));
+    }
+
+    #[test]
+    fn rolled_back_with_transaction() {
+        let mut storage = Storage::<u64, u64>::new();
+        let by_value = storage.add_index(|value: &u64| *value);
+
+        let mut block = storage.block();
+
+        {
+            let mut transaction = block.transaction();
+            transaction.insert(0, 0);
+            transaction.apply();
+        }
+
+        {
+            let mut transaction = block.transaction();
+            *transaction.get_mut(&0).expect("inserted above") = 5;
+            transaction.insert(1, 1);
+        }
+
+        block.commit();
+
+        let view = storage.view();
+        assert!(view.index(&by_value).iter().eq([(&0, &0)]));
+    }
+
+    #[test]
+    fn reverted_with_block() {
+        let mut storage = Storage::<u64, u64>::new();
+        let by_value = storage.add_index(|value: &u64| *value);
+
+        {
+            let mut block = storage.block();
+            block.insert(0, 0);
+            block.commit();
+        }
+
+        {
+            let mut block = storage.block();
+            block.insert(0, 3);
+            block.insert(1, 3);
+            block.commit();
+        }
+
+        let view1 = storage.view();
+
+        storage.block_and_revert().commit();
+
+        let view2 = storage.view();
+
+        assert!(view1.index(&by_value).range(1..).eq([(&3, &0), (&3, &1)]));
+        assert!(view2.index(&by_value).iter().eq([(&0, &0)]));
+    }
+
+    #[test]
+    #[should_panic(expected = "index handle belongs to another storage")]
+    fn handle_of_another_storage() {
+        let mut storage = Storage::<u64, u64>::from_iter([(0, 0)]);
+        let mut other = Storage::<u64, u64>::from_iter([(0, 1)]);
+        let _by_value = storage.add_index(|value: &u64| *value);
+        let other_by_value = other.add_index(|value: &u64| *value);
+
+        // Index at the same position of the storage with the same types
+        let _ = storage.view().index(&other_by_value).iter().count();
+    }
+
+    proptest! {
+        #[test]
+        fn consistent_with_recomputed_index(txs: Vec<(bool, Vec<(u8, Option<u8>)>)>) {
+            let mut storage = Storage::<u8, u8>::new();
+            let by_value = storage.add_index(|value: &u8| *value / 4);
+            let mut map = BTreeMap::new();
+
+            let mut block = storage.block();
+            for (applied, tx) in txs {
+                let mut transaction = block.transaction();
+                let mut changes = map.clone();
+                for (key, value) in tx {
+                    match value {
+                        Some(value) if value.is_multiple_of(2) => {
+                            changes.insert(key, value);
+                            transaction.insert(key, value);
+                        }
+                        Some(value) => {
+                            if let Some(stored) = transaction.get_mut(&key) {
+                                *stored = value;
+                                changes.insert(key, value);
+                            }
+                        }
+                        None => {
+                            changes.remove(&key);
+                            transaction.remove(key);
+                        }
+                    }
+                }
+                if applied {
+                    map = changes;
+                    transaction.apply();
+                }
+            }
+            block.commit();
+
+            let view = storage.view();
+            let mut expected = map.iter().map(|(key, value)| (value / 4, *key)).collect::<Vec<_>>();
+            expected.sort();
+            assert!(view.index(&by_value).iter().map(|(value, key)| (*value, *key)).eq(expected));
+        }
+    }
+}
@@ -1,12 +1,28 @@
-use core::fmt::Debug;
+use core::{fmt::Debug, hash::Hash};
 
+mod bounded;
 pub mod cell;
+pub mod codec;
+pub mod coordinator;
+pub mod error;
+pub mod hash_storage;
+pub mod index;
+pub mod multimap;
 #[cfg(feature = "serde")]
 pub mod serde;
+pub mod set;
+pub mod sharded;
+pub mod snapshot;
 pub mod storage;
+pub mod vec;
+mod version;
+
+pub use error::Error;
 
 pub trait Key: Clone + Ord + Debug + Send + Sync + 'static {}
 pub trait Value: Clone + Send + Sync + 'static {}
+pub trait HashKey: Clone + Eq + Hash + Debug + Send + Sync + 'static {}
 
 impl<T: Clone + Ord + Debug + Send + Sync + 'static> Key for T {}
 impl<T: Clone + Send + Sync + 'static> Value for T {}
+impl<T: Clone + Eq + Hash + Debug + Send + Sync + 'static> HashKey for T {}
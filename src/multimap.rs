@@ -0,0 +1,375 @@
+//! Multi-version map from key to the ordered set of values
+
+use core::ops::Bound;
+
+use crate::{
+    bounded::Bounded,
+    storage::{self, Storage, StorageReadOnly},
+    Key,
+};
+
+/// Entries stored in the underlying storage, values are always [`Bounded::Value`]
+pub(crate) type Entry<K, V> = (K, Bounded<V>);
+
+/// Bounds of the entries range
+type EntryRange<K, V> = (Bound<Entry<K, V>>, Bound<Entry<K, V>>);
+
+/// Range containing all entries for the key
+fn key_range<K: Key, V: Key>(key: &K) -> EntryRange<K, V> {
+    (
+        Bound::Excluded((key.clone(), Bounded::Min)),
+        Bound::Excluded((key.clone(), Bounded::Max)),
+    )
+}
+
+/// Multi-version map from key to the ordered set of values
+pub struct StorageMultiMap<K: Key, V: Key> {
+    pub(crate) storage: Storage<Entry<K, V>, ()>,
+}
+
+impl<K: Key, V: Key> StorageMultiMap<K, V> {
+    /// Construct new [`Self`]
+    pub fn new() -> Self {
+        Self {
+            storage: Storage::new(),
+        }
+    }
+
+    /// Create persistent view of multimap at certain point in time
+    pub fn view(&self) -> View<'_, K, V> {
+        View {
+            view: self.storage.view(),
+        }
+    }
+
+    /// Create block to aggregate updates
+    pub fn block(&self) -> Block<'_, K, V> {
+        Block {
+            block: self.storage.block(),
+        }
+    }
+
+    /// Create block to aggregate updates and revert changes created in the latest block
+    pub fn block_and_revert(&self) -> Block<'_, K, V> {
+        Block {
+            block: self.storage.block_and_revert(),
+        }
+    }
+}
+
+impl<K: Key, V: Key> Default for StorageMultiMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Key, V: Key> FromIterator<(K, V)> for StorageMultiMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Self {
+            storage: iter
+                .into_iter()
+                .map(|(key, value)| ((key, Bounded::Value(value)), ()))
+                .collect(),
+        }
+    }
+}
+
+pub trait StorageMultiMapReadOnly<K: Key, V: Key> {
+    /// Iterate over values associated with the key in order
+    fn get(&self, key: &K) -> Values<'_, K, V>;
+
+    /// Check if value is associated with the key
+    fn contains(&self, key: &K, value: &V) -> bool;
+
+    /// Iterate over all key value pairs ordered by key and then by value
+    fn iter(&self) -> Iter<'_, K, V>;
+
+    /// Get amount of values associated with the key
+    fn count(&self, key: &K) -> usize {
+        self.get(key).count()
+    }
+
+    /// Get amount of key value pairs in the multimap
+    fn len(&self) -> usize;
+
+    /// Check if multimap has no entries
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Implement [`StorageMultiMapReadOnly`] by delegating to the underlying storage type
+macro_rules! impl_multimap_read_only {
+    ($ty:ty, $field:ident) => {
+        impl<K: Key, V: Key> StorageMultiMapReadOnly<K, V> for $ty {
+            fn get(&self, key: &K) -> Values<'_, K, V> {
+                Values {
+                    iter: self.$field.range(key_range(key)),
+                }
+            }
+
+            fn contains(&self, key: &K, value: &V) -> bool {
+                self.$field
+                    .get(&(key.clone(), Bounded::Value(value.clone())))
+                    .is_some()
+            }
+
+            fn iter(&self) -> Iter<'_, K, V> {
+                Iter {
+                    iter: self.$field.iter(),
+                }
+            }
+
+            fn len(&self) -> usize {
+                self.$field.len()
+            }
+        }
+    };
+}
+
+/// Module for [`View`] and it's related impls
+mod view {
+    use super::*;
+
+    /// Consistent view of the multimap at the certain version
+    pub struct View<'storage, K: Key, V: Key> {
+        pub(crate) view: storage::View<'storage, Entry<K, V>, ()>,
+    }
+
+    impl_multimap_read_only!(View<'_, K, V>, view);
+}
+pub use view::View;
+
+/// Module for [`Block`] and it's related impls
+mod block {
+    use super::*;
+
+    /// Batched update to the multimap that can be reverted later
+    pub struct Block<'store, K: Key, V: Key> {
+        pub(crate) block: storage::Block<'store, Entry<K, V>, ()>,
+    }
+
+    impl<'store, K: Key, V: Key> Block<'store, K, V> {
+        /// Create transaction for the block
+        pub fn transaction<'block>(&'block mut self) -> Transaction<'block, 'store, K, V>
+        where
+            'store: 'block,
+        {
+            Transaction {
+                transaction: self.block.transaction(),
+            }
+        }
+
+        /// Apply aggregated changes to the multimap
+        pub fn commit(self) {
+            self.block.commit();
+        }
+
+        /// Associate value with the key, return `true` if value wasn't associated with the key
+        pub fn insert(&mut self, key: K, value: V) -> bool {
+            self.block
+                .insert((key, Bounded::Value(value)), ())
+                .is_none()
+        }
+
+        /// Remove value associated with the key, return `true` if value was associated with the key
+        pub fn remove(&mut self, key: K, value: V) -> bool {
+            self.block.remove((key, Bounded::Value(value))).is_some()
+        }
+
+        /// Remove all values associated with the key, return amount of removed values
+        pub fn remove_all(&mut self, key: K) -> usize {
+            let entries = self
+                .block
+                .range(key_range::<K, V>(&key))
+                .map(|(entry, ())| entry.clone())
+                .collect::<Vec<_>>();
+            let removed = entries.len();
+            for entry in entries {
+                self.block.remove(entry);
+            }
+            removed
+        }
+    }
+
+    impl_multimap_read_only!(Block<'_, K, V>, block);
+
+    /// Part of block's aggregated changes which applied or aborted at the same time
+    pub struct Transaction<'block, 'store, K: Key, V: Key> {
+        pub(crate) transaction: storage::Transaction<'block, 'store, Entry<K, V>, ()>,
+    }
+
+    impl<'block, 'store: 'block, K: Key, V: Key> Transaction<'block, 'store, K, V> {
+        /// Apply aggregated changes of [`Transaction`] to the [`Block`]
+        pub fn apply(self) {
+            self.transaction.apply();
+        }
+
+        /// Associate value with the key, return `true` if value wasn't associated with the key
+        pub fn insert(&mut self, key: K, value: V) -> bool {
+            self.transaction
+                .insert((key, Bounded::Value(value)), ())
+                .is_none()
+        }
+
+        /// Remove value associated with the key, return `true` if value was associated with the key
+        pub fn remove(&mut self, key: K, value: V) -> bool {
+            self.transaction
+                .remove((key, Bounded::Value(value)))
+                .is_some()
+        }
+
+        /// Remove all values associated with the key, return amount of removed values
+        pub fn remove_all(&mut self, key: K) -> usize {
+            let entries = self
+                .transaction
+                .range(key_range::<K, V>(&key))
+                .map(|(entry, ())| entry.clone())
+                .collect::<Vec<_>>();
+            let removed = entries.len();
+            for entry in entries {
+                self.transaction.remove(entry);
+            }
+            removed
+        }
+    }
+
+    impl_multimap_read_only!(Transaction<'_, '_, K, V>, transaction);
+}
+pub use block::{Block, Transaction};
+
+mod iter {
+    use super::*;
+
+    /// Iterate over values associated with the single key
+    pub struct Values<'slf, K: Key, V: Key> {
+        pub(crate) iter: storage::RangeIter<'slf, Entry<K, V>, ()>,
+    }
+
+    /// Iterate over all key value pairs
+    pub struct Iter<'slf, K: Key, V: Key> {
+        pub(crate) iter: storage::Iter<'slf, Entry<K, V>, ()>,
+    }
+
+    impl<'slf, K: Key, V: Key> Iterator for Values<'slf, K, V> {
+        type Item = &'slf V;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            self.iter.next().map(|((_, value), ())| value.value())
+        }
+    }
+
+    impl<'slf, K: Key, V: Key> Iterator for Iter<'slf, K, V> {
+        type Item = (&'slf K, &'slf V);
+
+        fn next(&mut self) -> Option<Self::Item> {
+            self.iter
+                .next()
+                .map(|((key, value), ())| (key, value.value()))
+        }
+    }
+}
+pub use iter::{Iter, Values};
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use super::*;
+
+    use proptest::proptest;
+
+    #[test]
+    fn insert_remove() {
+        let multimap = StorageMultiMap::<u64, u64>::new();
+
+        {
+            let mut block = multimap.block();
+            assert!(block.insert(1, 3));
+            assert!(block.insert(1, 1));
+            assert!(!block.insert(1, 1));
+            block.insert(0, 5);
+            block.insert(2, 0);
+            block.commit();
+        }
+
+        let view1 = multimap.view();
+
+        {
+            let mut block = multimap.block();
+            assert!(block.remove(1, 3));
+            assert!(!block.remove(1, 3));
+            assert_eq!(block.remove_all(2), 1);
+            block.commit();
+        }
+
+        let view2 = multimap.view();
+
+        assert!(view1.get(&1).copied().eq([1, 3]));
+        assert_eq!(view1.count(&1), 2);
+        assert_eq!(view1.len(), 4);
+        assert!(view1.contains(&2, &0));
+        assert!(view2.get(&1).copied().eq([1]));
+        assert_eq!(view2.count(&2), 0);
+        assert!(view2.iter().eq([(&0, &5), (&1, &1)]));
+
+        multimap.block_and_revert().commit();
+
+        let view3 = multimap.view();
+        assert!(view3.iter().eq(view1.iter()));
+    }
+
+    #[test]
+    fn transaction_step() {
+        let multimap = StorageMultiMap::<u64, u64>::new();
+
+        let mut block = multimap.block();
+
+        {
+            let mut transaction = block.transaction();
+            transaction.insert(0, 0);
+            transaction.insert(0, 1);
+            transaction.apply();
+        }
+
+        {
+            let mut transaction = block.transaction();
+            transaction.remove_all(0);
+            transaction.insert(0, 2);
+        }
+
+        block.commit();
+
+        let view = multimap.view();
+        assert!(view.get(&0).copied().eq([0, 1]));
+    }
+
+    proptest! {
+        #[test]
+        fn consistent_with_btreemap(ops: Vec<(u8, Option<u8>)>) {
+            let multimap = StorageMultiMap::<u8, u8>::new();
+            let mut map = BTreeMap::<u8, BTreeSet<u8>>::new();
+
+            let mut block = multimap.block();
+            for (key, value) in ops {
+                match value {
+                    Some(value) => {
+                        map.entry(key).or_default().insert(value);
+                        block.insert(key, value);
+                    }
+                    None => {
+                        let removed = map.remove(&key).map_or(0, |values| values.len());
+                        assert_eq!(block.remove_all(key), removed);
+                    }
+                }
+            }
+            block.commit();
+
+            let view = multimap.view();
+            for key in 0..=u8::MAX {
+                let expected = map.get(&key).cloned().unwrap_or_default();
+                assert!(view.get(&key).eq(expected.iter()));
+            }
+        }
+    }
+}
@@ -1,23 +1,45 @@
 //! Module with serialization and deserialization of multi version storage
 
 use core::fmt;
-use std::{collections::BTreeMap, ops::Deref};
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    ops::Deref,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+};
 
 use serde::{
     de::{self, DeserializeSeed, MapAccess, SeqAccess, Visitor},
-    ser::{SerializeMap, SerializeStruct},
+    ser::{SerializeMap, SerializeSeq, SerializeStruct},
     Deserialize, Deserializer, Serialize,
 };
 
-use crate::{Key, Value};
+use crate::{version::Version, HashKey, Key, Value};
 
-pub use self::{cell::CellSeeded, storage::StorageSeeded};
+pub use self::{
+    cell::{CellSeeded, ValidatingCellSeeded},
+    hash_storage::HashStorageSeeded,
+    set::StorageSetSeeded,
+    storage::{
+        Header, Migration, NoMigration, NoSeed, NoValidation, StorageSeeded, StorageSeededWith,
+        Structural, TypeTag, Validation, FORMAT_VERSION, MAGIC,
+    },
+};
 
 mod storage {
-    use crate::storage::Storage;
+    use crate::storage::{BlockMeta, Quota, Redo, Storage, StorageReadOnly, View};
 
     use super::*;
 
+    /// Magic string which starts every storage snapshot
+    pub const MAGIC: &str = "mv::Storage";
+
+    /// Version of the snapshot layout written by this crate
+    ///
+    /// Format `0` is the unframed `{revert, blocks}` layout, format `1` has no metadata,
+    /// format `2` doesn't record whether revert holds changes of the latest block, format `3` has no redo.
+    pub const FORMAT_VERSION: u32 = 4;
+
     /// Struct to deserialize [`Storage`] with provided seed for keys and values
     /// In case seed is only required for keys or values use [`PhantomData`] in place where seed is not required.
     pub struct StorageSeeded<KS, VS> {
@@ -25,21 +47,341 @@ mod storage {
         pub vseed: VS,
     }
 
-    impl<K: Serialize + Key, V: Serialize + Value> Serialize for Storage<K, V> {
+    /// Struct to deserialize [`Storage`] upgrading snapshots with outdated header and validating loaded entries,
+    /// created by [`StorageSeeded::with_migration`] or [`StorageSeeded::with_validation`]
+    pub struct StorageSeededWith<KS, VS, M, C> {
+        pub kseed: KS,
+        pub vseed: VS,
+        pub migration: M,
+        pub validation: C,
+    }
+
+    /// Header of the storage snapshot
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Header {
+        /// Version of the snapshot layout
+        pub format: u32,
+        /// Version of the storage at which snapshot was taken
+        pub version: u64,
+        /// [`TypeTag`] of storage keys, `None` for unframed snapshots
+        pub key_type: Option<String>,
+        /// [`TypeTag`] of storage values, `None` for unframed snapshots
+        pub value_type: Option<String>,
+    }
+
+    impl Header {
+        /// Check if snapshot has the latest format and was written for storage of the same types
+        pub fn is_current<K: TypeTag, V: TypeTag>(&self) -> bool {
+            self.format == FORMAT_VERSION
+                && self.key_type.as_deref() == Some(&*K::tag())
+                && self.value_type.as_deref() == Some(&*V::tag())
+        }
+    }
+
+    /// Stable name of the type written to the snapshot header
+    ///
+    /// Unlike [`core::any::type_name`] tag doesn't depend on compiler version or module path,
+    /// so it must be kept when type is moved and changed when it's serialized schema changes.
+    /// Tags of the compound types are built from tags of their parts, e.g. `Vec<(u64, String)>`.
+    pub trait TypeTag {
+        /// Name of the type
+        fn tag() -> Cow<'static, str>;
+    }
+
+    macro_rules! impl_type_tag {
+        ($($ty:ty),*) => {$(
+            impl TypeTag for $ty {
+                fn tag() -> Cow<'static, str> {
+                    Cow::Borrowed(stringify!($ty))
+                }
+            }
+        )*};
+    }
+
+    impl_type_tag!(
+        (),
+        bool,
+        char,
+        u8,
+        u16,
+        u32,
+        u64,
+        u128,
+        usize,
+        i8,
+        i16,
+        i32,
+        i64,
+        i128,
+        isize,
+        f32,
+        f64,
+        String
+    );
+
+    /// Pointer is serialized as the value it points to
+    impl<T: TypeTag> TypeTag for std::sync::Arc<T> {
+        fn tag() -> Cow<'static, str> {
+            T::tag()
+        }
+    }
+
+    /// Pointer is serialized as the value it points to
+    impl<T: TypeTag> TypeTag for Box<T> {
+        fn tag() -> Cow<'static, str> {
+            T::tag()
+        }
+    }
+
+    impl<T: TypeTag> TypeTag for Option<T> {
+        fn tag() -> Cow<'static, str> {
+            Cow::Owned(format!("Option<{}>", T::tag()))
+        }
+    }
+
+    impl<T: TypeTag> TypeTag for Vec<T> {
+        fn tag() -> Cow<'static, str> {
+            Cow::Owned(format!("Vec<{}>", T::tag()))
+        }
+    }
+
+    impl<T: TypeTag, const N: usize> TypeTag for [T; N] {
+        fn tag() -> Cow<'static, str> {
+            Cow::Owned(format!("[{}; {N}]", T::tag()))
+        }
+    }
+
+    impl<T: TypeTag> TypeTag for std::collections::BTreeSet<T> {
+        fn tag() -> Cow<'static, str> {
+            Cow::Owned(format!("BTreeSet<{}>", T::tag()))
+        }
+    }
+
+    impl<K: TypeTag, V: TypeTag> TypeTag for BTreeMap<K, V> {
+        fn tag() -> Cow<'static, str> {
+            Cow::Owned(format!("BTreeMap<{}, {}>", K::tag(), V::tag()))
+        }
+    }
+
+    macro_rules! impl_type_tag_for_tuples {
+        ($(($($ty:ident),+)),*) => {$(
+            impl<$($ty: TypeTag),+> TypeTag for ($($ty,)+) {
+                fn tag() -> Cow<'static, str> {
+                    let tags: &[Cow<'static, str>] = &[$($ty::tag()),+];
+                    // Single element tuple keeps trailing comma as in Rust syntax
+                    let comma = if tags.len() == 1 { "," } else { "" };
+                    Cow::Owned(format!("({}{comma})", tags.join(", ")))
+                }
+            }
+        )*};
+    }
+
+    impl_type_tag_for_tuples!(
+        (A),
+        (A, B),
+        (A, B, C),
+        (A, B, C, D),
+        (A, B, C, D, E),
+        (A, B, C, D, E, F)
+    );
+
+    /// Hook to load snapshots with outdated header into storage of the current types
+    ///
+    /// Seeds are chosen from the header before any entry is decoded,
+    /// so snapshots written with the previous schema of keys or values can be upgraded while loading.
+    /// Therefore header fields must precede `revert`, `redo` and `blocks`, as they are written by [`Storage`](crate::storage::Storage).
+    pub trait Migration<'de, K, V> {
+        /// Seed which decodes key of the outdated snapshot
+        type KeySeed: DeserializeSeed<'de, Value = K> + Clone;
+        /// Seed which decodes value of the outdated snapshot
+        type ValueSeed: DeserializeSeed<'de, Value = V> + Clone;
+
+        /// Choose seeds for the snapshot with outdated `header`
+        ///
+        /// `None` decodes snapshot with seeds of the current types.
+        #[allow(clippy::type_complexity)]
+        fn seeds(
+            &self,
+            header: &Header,
+        ) -> Result<Option<(Self::KeySeed, Self::ValueSeed)>, String>;
+    }
+
+    /// Seed which can't be constructed, used by migrations which never replace seeds
+    pub enum NoSeed<T> {
+        #[doc(hidden)]
+        Never(core::convert::Infallible, core::marker::PhantomData<T>),
+    }
+
+    impl<T> Clone for NoSeed<T> {
+        fn clone(&self) -> Self {
+            match *self {
+                Self::Never(never, _) => match never {},
+            }
+        }
+    }
+
+    impl<'de, T> DeserializeSeed<'de> for NoSeed<T> {
+        type Value = T;
+
+        fn deserialize<D>(self, _deserializer: D) -> Result<T, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            match self {
+                Self::Never(never, _) => match never {},
+            }
+        }
+    }
+
+    /// Migration which accepts older formats as is and rejects snapshots of other types
+    pub struct NoMigration;
+
+    impl<'de, K: TypeTag, V: TypeTag> Migration<'de, K, V> for NoMigration {
+        type KeySeed = NoSeed<K>;
+        type ValueSeed = NoSeed<V>;
+
+        fn seeds(
+            &self,
+            header: &Header,
+        ) -> Result<Option<(Self::KeySeed, Self::ValueSeed)>, String> {
+            let matches = |tag: &Option<String>, expected: &str| {
+                tag.as_deref().is_none_or(|tag| tag == expected)
+            };
+            let (key_type, value_type) = (K::tag(), V::tag());
+            if matches(&header.key_type, &key_type) && matches(&header.value_type, &value_type) {
+                Ok(None)
+            } else {
+                Err(format!(
+                    "snapshot of Storage<{}, {}> can't be loaded as Storage<{}, {}>",
+                    header.key_type.as_deref().unwrap_or_default(),
+                    header.value_type.as_deref().unwrap_or_default(),
+                    key_type,
+                    value_type,
+                ))
+            }
+        }
+    }
+
+    /// Seed of the current types or the one chosen by migration
+    #[derive(Clone)]
+    enum Seed<S, M> {
+        Current(S),
+        Migrated(M),
+    }
+
+    impl<'de, S, M> DeserializeSeed<'de> for Seed<S, M>
+    where
+        S: DeserializeSeed<'de>,
+        M: DeserializeSeed<'de, Value = S::Value>,
+    {
+        type Value = S::Value;
+
+        fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            match self {
+                Self::Current(seed) => seed.deserialize(deserializer),
+                Self::Migrated(seed) => seed.deserialize(deserializer),
+            }
+        }
+    }
+
+    /// Checks of the storage entries performed during deserialization
+    ///
+    /// Validation rejects `blocks`, `revert` and `redo` maps whose keys are not strictly increasing
+    /// and entries which fail user supplied predicates.
+    pub trait Validation<K, V> {
+        /// Whether validation is performed at all
+        const ENABLED: bool = true;
+
+        /// Check entry of the storage
+        fn entry(&self, _key: &K, _value: &V) -> Result<(), String> {
+            Ok(())
+        }
+
+        /// Check value of the key before the latest block, `current` is the value after it
+        fn revert(&self, _key: &K, _prev: Option<&V>, _current: Option<&V>) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    /// Entries are accepted as is
+    pub struct NoValidation;
+
+    impl<K, V> Validation<K, V> for NoValidation {
+        const ENABLED: bool = false;
+    }
+
+    /// Only structural invariants are checked
+    pub struct Structural;
+
+    impl<K, V> Validation<K, V> for Structural {}
+
+    /// Closure is used as a predicate over storage entries
+    impl<K, V, F> Validation<K, V> for F
+    where
+        F: Fn(&K, &V) -> Result<(), String>,
+    {
+        fn entry(&self, key: &K, value: &V) -> Result<(), String> {
+            self(key, value)
+        }
+
+        fn revert(&self, key: &K, prev: Option<&V>, _current: Option<&V>) -> Result<(), String> {
+            prev.map_or(Ok(()), |prev| self(key, prev))
+        }
+    }
+
+    impl<K: Serialize + Key + TypeTag, V: Serialize + Value + TypeTag> Serialize for Storage<K, V> {
         fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
         where
             S: serde::Serializer,
         {
-            let revert = self.revert.read();
-            let blocks = self.blocks.read();
-
-            let mut state = serializer.serialize_struct("Storage", 2)?;
+            // Retry if commit is published while revert and blocks are opened, so that they are from the same commit
+            let (version, meta, revertible, revert, redo, blocks) = self.version.read(|version| {
+                (
+                    version,
+                    self.meta.read(),
+                    self.revertible.load(Ordering::Relaxed),
+                    self.revert.read(),
+                    self.redo.read(),
+                    self.blocks.read(),
+                )
+            });
+            // Redo is kept only while the revert is the latest commit
+            let redoable = redo.version == Some(version);
+            let no_redo = BTreeMap::new();
+
+            let mut state = serializer.serialize_struct("Storage", 11)?;
+            state.serialize_field("magic", MAGIC)?;
+            state.serialize_field("format", &FORMAT_VERSION)?;
+            state.serialize_field("version", &version)?;
+            state.serialize_field("key_type", &K::tag())?;
+            state.serialize_field("value_type", &V::tag())?;
+            state.serialize_field("meta", &meta.as_deref().map(|meta| &meta.bytes))?;
+            state.serialize_field("revertible", &revertible)?;
+            state.serialize_field("redoable", &redoable)?;
             state.serialize_field("revert", revert.deref())?;
+            state.serialize_field("redo", if redoable { &redo.changes } else { &no_redo })?;
             state.serialize_field("blocks", &BlocksSerializeHelper(blocks))?;
             state.end()
         }
     }
 
+    /// Serialize entries of the view as a map, revert is not included
+    impl<K: Serialize + Key, V: Serialize + Value> Serialize for View<'_, K, V> {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: serde::Serializer,
+        {
+            let mut map = serializer.serialize_map(Some(self.len()))?;
+            for (k, v) in self.iter() {
+                map.serialize_entry(k, v)?;
+            }
+            map.end()
+        }
+    }
+
     struct BlocksSerializeHelper<'block, K: Key, V: Value>(
         concread::bptree::BptreeMapReadTxn<'block, K, V>,
     );
@@ -57,8 +399,10 @@ mod storage {
         }
     }
 
-    impl<'de, K: Deserialize<'de> + Key, V: Deserialize<'de> + Value> Deserialize<'de>
-        for Storage<K, V>
+    impl<'de, K, V> Deserialize<'de> for Storage<K, V>
+    where
+        K: Deserialize<'de> + Key + TypeTag,
+        V: Deserialize<'de> + Value + TypeTag,
     {
         fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
         where
@@ -72,12 +416,63 @@ mod storage {
         }
     }
 
+    impl<KS, VS> StorageSeeded<KS, VS> {
+        /// Upgrade snapshots with outdated [`Header`] using `migration`
+        pub fn with_migration<M>(self, migration: M) -> StorageSeededWith<KS, VS, M, NoValidation> {
+            StorageSeededWith {
+                kseed: self.kseed,
+                vseed: self.vseed,
+                migration,
+                validation: NoValidation,
+            }
+        }
+
+        /// Reject snapshots which fail `validation`
+        pub fn with_validation<C>(
+            self,
+            validation: C,
+        ) -> StorageSeededWith<KS, VS, NoMigration, C> {
+            self.with_migration(NoMigration).with_validation(validation)
+        }
+    }
+
+    impl<KS, VS, M, C> StorageSeededWith<KS, VS, M, C> {
+        /// Reject snapshots which fail `validation`
+        pub fn with_validation<C2>(self, validation: C2) -> StorageSeededWith<KS, VS, M, C2> {
+            StorageSeededWith {
+                kseed: self.kseed,
+                vseed: self.vseed,
+                migration: self.migration,
+                validation,
+            }
+        }
+    }
+
     impl<'de, KS, VS> DeserializeSeed<'de> for StorageSeeded<KS, VS>
     where
         KS: DeserializeSeed<'de> + Clone,
         VS: DeserializeSeed<'de> + Clone,
-        KS::Value: Key,
-        VS::Value: Value,
+        KS::Value: Key + TypeTag,
+        VS::Value: Value + TypeTag,
+    {
+        type Value = Storage<KS::Value, VS::Value>;
+
+        fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            self.with_migration(NoMigration).deserialize(deserializer)
+        }
+    }
+
+    impl<'de, KS, VS, M, C> DeserializeSeed<'de> for StorageSeededWith<KS, VS, M, C>
+    where
+        KS: DeserializeSeed<'de> + Clone,
+        VS: DeserializeSeed<'de> + Clone,
+        KS::Value: Key + TypeTag,
+        VS::Value: Value + TypeTag,
+        M: Migration<'de, KS::Value, VS::Value>,
+        C: Validation<KS::Value, VS::Value>,
     {
         type Value = Storage<KS::Value, VS::Value>;
 
@@ -86,7 +481,16 @@ mod storage {
             D: serde::Deserializer<'de>,
         {
             enum Field {
+                Magic,
+                Format,
+                Version,
+                KeyType,
+                ValueType,
+                Meta,
+                Revertible,
+                Redoable,
                 Revert,
+                Redo,
                 Blocks,
             }
 
@@ -101,7 +505,9 @@ mod storage {
                         type Value = Field;
 
                         fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                            formatter.write_str("`revert` or `blocks`")
+                            formatter.write_str(
+                                "`magic`, `format`, `version`, `key_type`, `value_type`, `meta`, `revertible`, `redoable`, `revert`, `redo` or `blocks`",
+                            )
                         }
 
                         fn visit_str<E>(self, value: &str) -> Result<Field, E>
@@ -109,7 +515,16 @@ mod storage {
                             E: de::Error,
                         {
                             match value {
+                                "magic" => Ok(Field::Magic),
+                                "format" => Ok(Field::Format),
+                                "version" => Ok(Field::Version),
+                                "key_type" => Ok(Field::KeyType),
+                                "value_type" => Ok(Field::ValueType),
+                                "meta" => Ok(Field::Meta),
+                                "revertible" => Ok(Field::Revertible),
+                                "redoable" => Ok(Field::Redoable),
                                 "revert" => Ok(Field::Revert),
+                                "redo" => Ok(Field::Redo),
                                 "blocks" => Ok(Field::Blocks),
                                 _ => Err(de::Error::unknown_field(value, FIELDS)),
                             }
@@ -120,25 +535,148 @@ mod storage {
                 }
             }
 
-            struct StorageSeededVisitor<KS, VS> {
+            struct StorageSeededVisitor<KS, VS, M, C> {
                 kseed: KS,
                 vseed: VS,
+                migration: M,
+                validation: C,
+            }
+
+            /// Header of the snapshot from the fields read before its entries
+            fn header<E: de::Error>(
+                magic: Option<String>,
+                format: Option<u32>,
+                version: Option<u64>,
+                key_type: Option<String>,
+                value_type: Option<String>,
+            ) -> Result<(String, Header), E> {
+                // Snapshots written before framing was introduced have no header
+                if magic.is_none()
+                    && format.is_none()
+                    && version.is_none()
+                    && key_type.is_none()
+                    && value_type.is_none()
+                {
+                    let header = Header {
+                        format: 0,
+                        version: 0,
+                        key_type: None,
+                        value_type: None,
+                    };
+                    return Ok((MAGIC.to_owned(), header));
+                }
+                let header = Header {
+                    format: format.ok_or_else(|| de::Error::missing_field("format"))?,
+                    version: version.ok_or_else(|| de::Error::missing_field("version"))?,
+                    key_type: Some(key_type.ok_or_else(|| de::Error::missing_field("key_type"))?),
+                    value_type: Some(
+                        value_type.ok_or_else(|| de::Error::missing_field("value_type"))?,
+                    ),
+                };
+                Ok((
+                    magic.ok_or_else(|| de::Error::missing_field("magic"))?,
+                    header,
+                ))
+            }
+
+            impl<KS, VS, M, C> StorageSeededVisitor<KS, VS, M, C> {
+                /// Check header of the snapshot and choose seeds decoding its entries,
+                /// snapshots with outdated header are decoded by seeds of the migration
+                #[allow(clippy::type_complexity)]
+                fn seeds<'de, E: de::Error>(
+                    &self,
+                    magic: &str,
+                    header: &Header,
+                ) -> Result<(Seed<KS, M::KeySeed>, Seed<VS, M::ValueSeed>), E>
+                where
+                    KS: DeserializeSeed<'de> + Clone,
+                    VS: DeserializeSeed<'de> + Clone,
+                    KS::Value: TypeTag,
+                    VS::Value: TypeTag,
+                    M: Migration<'de, KS::Value, VS::Value>,
+                {
+                    if magic != MAGIC {
+                        return Err(de::Error::invalid_value(de::Unexpected::Str(magic), &MAGIC));
+                    }
+                    if header.format > FORMAT_VERSION {
+                        return Err(de::Error::custom(format_args!(
+                            "unsupported snapshot format {}, latest supported is {}",
+                            header.format, FORMAT_VERSION
+                        )));
+                    }
+                    let current = || {
+                        (
+                            Seed::Current(self.kseed.clone()),
+                            Seed::Current(self.vseed.clone()),
+                        )
+                    };
+                    if header.is_current::<KS::Value, VS::Value>() {
+                        return Ok(current());
+                    }
+                    Ok(
+                        match self.migration.seeds(header).map_err(de::Error::custom)? {
+                            Some((kseed, vseed)) => (Seed::Migrated(kseed), Seed::Migrated(vseed)),
+                            None => current(),
+                        },
+                    )
+                }
+
+                /// Build storage from the decoded snapshot and validate it
+                fn finish<K: Key, V: Value, E: de::Error>(
+                    self,
+                    header: Header,
+                    meta: Option<Vec<u8>>,
+                    revertible: bool,
+                    revert: BTreeMap<K, Option<V>>,
+                    redo: Option<BTreeMap<K, Option<V>>>,
+                    blocks: concread::bptree::BptreeMap<K, V>,
+                ) -> Result<Storage<K, V>, E>
+                where
+                    C: Validation<K, V>,
+                {
+                    let redo = redo.map_or_else(Redo::default, |changes| Redo {
+                        version: Some(header.version),
+                        changes,
+                    });
+                    let storage = Storage {
+                        revert: concread::EbrCell::new(revert),
+                        blocks,
+                        indexes: Vec::new(),
+                        version: Version::new(header.version),
+                        modified: None,
+                        tracked_since: AtomicU64::new(header.version),
+                        redo: concread::EbrCell::new(redo),
+                        revertible: AtomicBool::new(revertible),
+                        receipts: concread::EbrCell::new(Vec::new()),
+                        meta: concread::EbrCell::new(
+                            meta.map(|bytes| std::sync::Arc::new(BlockMeta::encoded(bytes))),
+                        ),
+                        quota: Quota::new(),
+                        bytes: AtomicUsize::new(0),
+                    };
+                    if C::ENABLED {
+                        validate(&storage, &self.validation)?;
+                    }
+                    Ok(storage)
+                }
             }
 
-            impl<'de, KS, VS> Visitor<'de> for StorageSeededVisitor<KS, VS>
+            impl<'de, KS, VS, M, C> Visitor<'de> for StorageSeededVisitor<KS, VS, M, C>
             where
                 KS: DeserializeSeed<'de> + Clone,
                 VS: DeserializeSeed<'de> + Clone,
-                KS::Value: Key,
-                VS::Value: Value,
+                KS::Value: Key + TypeTag,
+                VS::Value: Value + TypeTag,
+                M: Migration<'de, KS::Value, VS::Value>,
+                C: Validation<KS::Value, VS::Value>,
             {
                 type Value = Storage<KS::Value, VS::Value>;
 
                 fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
                     formatter.write_fmt(format_args!(
                         "struct Storage<{}, {}>",
-                        core::any::type_name::<KS::Value>(),
-                        core::any::type_name::<VS::Value>(),
+                        KS::Value::tag(),
+                        VS::Value::tag(),
                     ))
                 }
 
@@ -146,62 +684,244 @@ mod storage {
                 where
                     SA: SeqAccess<'de>,
                 {
+                    let magic: String = seq
+                        .next_element()?
+                        .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                    let format = seq
+                        .next_element()?
+                        .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                    let version = seq
+                        .next_element()?
+                        .ok_or_else(|| de::Error::invalid_length(2, &self))?;
+                    let key_type = seq
+                        .next_element()?
+                        .ok_or_else(|| de::Error::invalid_length(3, &self))?;
+                    let value_type = seq
+                        .next_element()?
+                        .ok_or_else(|| de::Error::invalid_length(4, &self))?;
+                    let header = Header {
+                        format,
+                        version,
+                        key_type: Some(key_type),
+                        value_type: Some(value_type),
+                    };
+                    let (kseed, vseed) = self.seeds(&magic, &header)?;
+                    // Metadata is written since format 2
+                    let mut len = 5;
+                    let meta = if header.format >= 2 {
+                        len += 1;
+                        seq.next_element()?
+                            .ok_or_else(|| de::Error::invalid_length(len - 1, &self))?
+                    } else {
+                        None
+                    };
+                    // Older formats don't record it, so their revert is not reverted
+                    let revertible = if header.format >= 3 {
+                        len += 1;
+                        seq.next_element()?
+                            .ok_or_else(|| de::Error::invalid_length(len - 1, &self))?
+                    } else {
+                        false
+                    };
+                    // Redo is written since format 4
+                    let redoable = if header.format >= 4 {
+                        len += 1;
+                        seq.next_element()?
+                            .ok_or_else(|| de::Error::invalid_length(len - 1, &self))?
+                    } else {
+                        false
+                    };
                     let revert = seq
                         .next_element_seed(RevertDeserializeSeeded {
-                            kseed: self.kseed.clone(),
-                            vseed: self.vseed.clone(),
+                            kseed: kseed.clone(),
+                            vseed: vseed.clone(),
+                            ordered: C::ENABLED,
                         })?
-                        .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                        .ok_or_else(|| de::Error::invalid_length(len, &self))?;
+                    let redo = if header.format >= 4 {
+                        len += 1;
+                        seq.next_element_seed(RevertDeserializeSeeded {
+                            kseed: kseed.clone(),
+                            vseed: vseed.clone(),
+                            ordered: C::ENABLED,
+                        })?
+                        .ok_or_else(|| de::Error::invalid_length(len, &self))?
+                    } else {
+                        BTreeMap::new()
+                    };
                     let blocks = seq
                         .next_element_seed(BlocksDeserializeSeeded {
-                            kseed: self.kseed.clone(),
-                            vseed: self.vseed.clone(),
+                            kseed,
+                            vseed,
+                            ordered: C::ENABLED,
                         })?
-                        .ok_or_else(|| de::Error::invalid_length(1, &self))?;
-                    Ok(Storage { revert, blocks })
+                        .ok_or_else(|| de::Error::invalid_length(len + 1, &self))?;
+                    let redo = redoable.then_some(redo);
+                    self.finish(header, meta, revertible, revert, redo, blocks)
                 }
 
                 fn visit_map<MA>(self, mut map: MA) -> Result<Self::Value, MA::Error>
                 where
                     MA: MapAccess<'de>,
                 {
+                    let mut magic: Option<String> = None;
+                    let mut format = None;
+                    let mut version = None;
+                    let mut key_type = None;
+                    let mut value_type = None;
+                    let mut meta: Option<Option<Vec<u8>>> = None;
+                    let mut revertible = None;
+                    let mut redoable = None;
+                    // Header and seeds chosen by it, entries can't be decoded before header is read
+                    let mut decoding = None;
                     let mut revert = None;
+                    let mut redo = None;
                     let mut blocks = None;
                     while let Some(key) = map.next_key()? {
+                        if decoding.is_some()
+                            && !matches!(
+                                key,
+                                Field::Revert
+                                    | Field::Redo
+                                    | Field::Blocks
+                                    | Field::Meta
+                                    | Field::Revertible
+                                    | Field::Redoable
+                            )
+                        {
+                            return Err(de::Error::custom(
+                                "snapshot header must precede its entries",
+                            ));
+                        }
                         match key {
-                            Field::Revert => {
-                                if revert.is_some() {
-                                    return Err(de::Error::duplicate_field("revert"));
+                            Field::Magic => {
+                                if magic.is_some() {
+                                    return Err(de::Error::duplicate_field("magic"));
                                 }
-                                revert = Some(map.next_value_seed(RevertDeserializeSeeded {
-                                    kseed: self.kseed.clone(),
-                                    vseed: self.vseed.clone(),
-                                })?);
+                                magic = Some(map.next_value()?);
                             }
-                            Field::Blocks => {
-                                if blocks.is_some() {
-                                    return Err(de::Error::duplicate_field("blocks"));
+                            Field::Format => {
+                                if format.is_some() {
+                                    return Err(de::Error::duplicate_field("format"));
+                                }
+                                format = Some(map.next_value()?);
+                            }
+                            Field::Version => {
+                                if version.is_some() {
+                                    return Err(de::Error::duplicate_field("version"));
+                                }
+                                version = Some(map.next_value()?);
+                            }
+                            Field::KeyType => {
+                                if key_type.is_some() {
+                                    return Err(de::Error::duplicate_field("key_type"));
+                                }
+                                key_type = Some(map.next_value()?);
+                            }
+                            Field::ValueType => {
+                                if value_type.is_some() {
+                                    return Err(de::Error::duplicate_field("value_type"));
+                                }
+                                value_type = Some(map.next_value()?);
+                            }
+                            Field::Meta => {
+                                if meta.is_some() {
+                                    return Err(de::Error::duplicate_field("meta"));
+                                }
+                                meta = Some(map.next_value()?);
+                            }
+                            Field::Revertible => {
+                                if revertible.is_some() {
+                                    return Err(de::Error::duplicate_field("revertible"));
+                                }
+                                revertible = Some(map.next_value()?);
+                            }
+                            Field::Redoable => {
+                                if redoable.is_some() {
+                                    return Err(de::Error::duplicate_field("redoable"));
+                                }
+                                redoable = Some(map.next_value()?);
+                            }
+                            Field::Revert | Field::Redo | Field::Blocks => {
+                                if decoding.is_none() {
+                                    let (magic, header) = header(
+                                        magic.take(),
+                                        format.take(),
+                                        version.take(),
+                                        key_type.take(),
+                                        value_type.take(),
+                                    )?;
+                                    let seeds = self.seeds(&magic, &header)?;
+                                    decoding = Some((header, seeds));
+                                }
+                                let (_, (kseed, vseed)) =
+                                    decoding.as_ref().expect("header is decoded above");
+                                if matches!(key, Field::Revert | Field::Redo) {
+                                    let (entries, name) = if matches!(key, Field::Revert) {
+                                        (&mut revert, "revert")
+                                    } else {
+                                        (&mut redo, "redo")
+                                    };
+                                    if entries.is_some() {
+                                        return Err(de::Error::duplicate_field(name));
+                                    }
+                                    *entries =
+                                        Some(map.next_value_seed(RevertDeserializeSeeded {
+                                            kseed: kseed.clone(),
+                                            vseed: vseed.clone(),
+                                            ordered: C::ENABLED,
+                                        })?);
+                                } else {
+                                    if blocks.is_some() {
+                                        return Err(de::Error::duplicate_field("blocks"));
+                                    }
+                                    blocks =
+                                        Some(map.next_value_seed(BlocksDeserializeSeeded {
+                                            kseed: kseed.clone(),
+                                            vseed: vseed.clone(),
+                                            ordered: C::ENABLED,
+                                        })?);
                                 }
-                                blocks = Some(map.next_value_seed(BlocksDeserializeSeeded {
-                                    kseed: self.kseed.clone(),
-                                    vseed: self.vseed.clone(),
-                                })?);
                             }
                         }
                     }
                     let revert = revert.ok_or_else(|| de::Error::missing_field("revert"))?;
                     let blocks = blocks.ok_or_else(|| de::Error::missing_field("blocks"))?;
-                    Ok(Storage { revert, blocks })
+                    let (header, _) = decoding.expect("header is decoded with the entries");
+                    // Older formats don't record them, so their revert is neither reverted nor redone
+                    let redo = redoable.unwrap_or(false).then(|| redo.unwrap_or_default());
+                    self.finish(
+                        header,
+                        meta.flatten(),
+                        revertible.unwrap_or(false),
+                        revert,
+                        redo,
+                        blocks,
+                    )
                 }
             }
 
-            const FIELDS: &[&str] = &["revert", "blocks"];
+            const FIELDS: &[&str] = &[
+                "magic",
+                "format",
+                "version",
+                "key_type",
+                "value_type",
+                "meta",
+                "revertible",
+                "redoable",
+                "revert",
+                "redo",
+                "blocks",
+            ];
             deserializer.deserialize_struct(
                 "Storage",
                 FIELDS,
                 StorageSeededVisitor {
                     kseed: self.kseed,
                     vseed: self.vseed,
+                    migration: self.migration,
+                    validation: self.validation,
                 },
             )
         }
@@ -210,6 +930,8 @@ mod storage {
     struct BlocksDeserializeSeeded<KS, VS> {
         kseed: KS,
         vseed: VS,
+        /// Reject keys which are not strictly increasing
+        ordered: bool,
     }
 
     impl<'de, KS, VS> DeserializeSeed<'de> for BlocksDeserializeSeeded<KS, VS>
@@ -228,6 +950,7 @@ mod storage {
             struct BlocksSeededVisitor<KS, VS> {
                 kseed: KS,
                 vseed: VS,
+                ordered: bool,
             }
 
             impl<
@@ -253,10 +976,18 @@ mod storage {
                 where
                     MA: MapAccess<'de>,
                 {
+                    let mut prev = None;
                     core::iter::from_fn(|| {
                         map.next_entry_seed(self.kseed.clone(), self.vseed.clone())
                             .transpose()
                     })
+                    .map(|entry| {
+                        let (key, value) = entry?;
+                        if self.ordered {
+                            check_order(&mut prev, &key, "blocks")?;
+                        }
+                        Ok((key, value))
+                    })
                     .collect::<Result<concread::bptree::BptreeMap<K, V>, MA::Error>>()
                 }
             }
@@ -264,6 +995,7 @@ mod storage {
             deserializer.deserialize_map(BlocksSeededVisitor {
                 kseed: self.kseed,
                 vseed: self.vseed,
+                ordered: self.ordered,
             })
         }
     }
@@ -271,6 +1003,8 @@ mod storage {
     struct RevertDeserializeSeeded<KS, VS> {
         kseed: KS,
         vseed: VS,
+        /// Reject keys which are not strictly increasing
+        ordered: bool,
     }
 
     impl<'de, KS, VS> DeserializeSeed<'de> for RevertDeserializeSeeded<KS, VS>
@@ -280,7 +1014,7 @@ mod storage {
         KS::Value: Key,
         VS::Value: Value,
     {
-        type Value = concread::ebrcell::EbrCell<BTreeMap<KS::Value, Option<VS::Value>>>;
+        type Value = BTreeMap<KS::Value, Option<VS::Value>>;
 
         fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
         where
@@ -289,6 +1023,7 @@ mod storage {
             struct RevertSeededVisitor<KS, VS> {
                 kseed: KS,
                 vseed: VS,
+                ordered: bool,
             }
 
             impl<
@@ -304,7 +1039,7 @@ mod storage {
                 KS::Value: Key,
                 VS::Value: Value,
             {
-                type Value = concread::ebrcell::EbrCell<BTreeMap<KS::Value, Option<VS::Value>>>;
+                type Value = BTreeMap<KS::Value, Option<VS::Value>>;
 
                 fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
                     formatter.write_str("a map")
@@ -314,6 +1049,7 @@ mod storage {
                 where
                     MA: MapAccess<'de>,
                 {
+                    let mut prev = None;
                     core::iter::from_fn(|| {
                         map.next_entry_seed(
                             self.kseed.clone(),
@@ -323,65 +1059,149 @@ mod storage {
                         )
                         .transpose()
                     })
+                    .map(|entry| {
+                        let (key, value) = entry?;
+                        if self.ordered {
+                            check_order(&mut prev, &key, "revert")?;
+                        }
+                        Ok((key, value))
+                    })
                     .collect::<Result<BTreeMap<_, _>, MA::Error>>()
-                    .map(concread::EbrCell::new)
                 }
             }
 
             deserializer.deserialize_map(RevertSeededVisitor {
                 kseed: self.kseed,
                 vseed: self.vseed,
+                ordered: self.ordered,
             })
         }
     }
+
+    /// Check that `key` is strictly greater than the previous key of the `map`
+    fn check_order<K: Key, E: de::Error>(
+        prev: &mut Option<K>,
+        key: &K,
+        map: &str,
+    ) -> Result<(), E> {
+        match prev.as_ref().map(|prev| prev.cmp(key)) {
+            Some(core::cmp::Ordering::Equal) => {
+                return Err(de::Error::custom(format_args!(
+                    "duplicate key {key:?} in `{map}`"
+                )))
+            }
+            Some(core::cmp::Ordering::Greater) => {
+                return Err(de::Error::custom(format_args!(
+                    "key {key:?} in `{map}` is out of order"
+                )))
+            }
+            _ => {}
+        }
+        *prev = Some(key.clone());
+        Ok(())
+    }
+
+    /// Check entries of the storage with user supplied predicates
+    fn validate<K: Key, V: Value, C: Validation<K, V>, E: de::Error>(
+        storage: &Storage<K, V>,
+        validation: &C,
+    ) -> Result<(), E> {
+        let blocks = storage.blocks.read();
+        for (key, value) in blocks.iter() {
+            validation.entry(key, value).map_err(|reason| {
+                de::Error::custom(format_args!("invalid entry {key:?} in `blocks`: {reason}"))
+            })?;
+        }
+        for (key, prev) in storage.revert.read().iter() {
+            validation
+                .revert(key, prev.as_ref(), blocks.get(key))
+                .map_err(|reason| {
+                    de::Error::custom(format_args!("invalid entry {key:?} in `revert`: {reason}"))
+                })?;
+        }
+        // Values replaced by the latest revert are also previous values of the latest block
+        for (key, next) in storage.redo.read().changes.iter() {
+            validation
+                .revert(key, next.as_ref(), blocks.get(key))
+                .map_err(|reason| {
+                    de::Error::custom(format_args!("invalid entry {key:?} in `redo`: {reason}"))
+                })?;
+        }
+        Ok(())
+    }
 }
 
-mod cell {
-    use concread::EbrCell;
+mod hash_storage {
+    use std::collections::HashMap as StdHashMap;
 
-    use crate::cell::Cell;
+    use crate::hash_storage::HashStorage;
 
     use super::*;
 
-    /// Struct to deserialize [`Storage`] with provided seed for keys and values
+    /// Struct to deserialize [`HashStorage`] with provided seed for keys and values
     /// In case seed is only required for keys or values use [`PhantomData`] in place where seed is not required.
-    pub struct CellSeeded<S> {
-        pub seed: S,
+    pub struct HashStorageSeeded<KS, VS> {
+        pub kseed: KS,
+        pub vseed: VS,
     }
 
-    impl<V: Serialize + Value> Serialize for Cell<V> {
+    impl<K: Serialize + HashKey, V: Serialize + Value> Serialize for HashStorage<K, V> {
         fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
         where
             S: serde::Serializer,
         {
-            let revert = self.revert.read();
-            let blocks = self.blocks.read();
+            // Retry if commit is published while revert and blocks are opened, so that they are from the same commit
+            let (revert, blocks) = self
+                .version
+                .read(|_| (self.revert.read(), self.blocks.read()));
 
-            let mut state = serializer.serialize_struct("Storage", 2)?;
+            let mut state = serializer.serialize_struct("HashStorage", 2)?;
             state.serialize_field("revert", revert.deref())?;
-            state.serialize_field("blocks", blocks.deref())?;
+            state.serialize_field("blocks", &BlocksSerializeHelper(blocks))?;
             state.end()
         }
     }
 
-    impl<'de, V: Deserialize<'de> + Value> Deserialize<'de> for Cell<V> {
+    struct BlocksSerializeHelper<'block, K: HashKey, V: Value>(
+        concread::hashmap::HashMapReadTxn<'block, K, V>,
+    );
+
+    impl<K: Serialize + HashKey, V: Serialize + Value> Serialize for BlocksSerializeHelper<'_, K, V> {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: serde::Serializer,
+        {
+            let mut map = serializer.serialize_map(Some(self.0.len()))?;
+            for (k, v) in self.0.iter() {
+                map.serialize_entry(k, v)?;
+            }
+            map.end()
+        }
+    }
+
+    impl<'de, K: Deserialize<'de> + HashKey, V: Deserialize<'de> + Value> Deserialize<'de>
+        for HashStorage<K, V>
+    {
         fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
         where
             D: serde::Deserializer<'de>,
         {
-            CellSeeded {
-                seed: core::marker::PhantomData::<V>,
+            HashStorageSeeded {
+                kseed: core::marker::PhantomData::<K>,
+                vseed: core::marker::PhantomData::<V>,
             }
             .deserialize(deserializer)
         }
     }
 
-    impl<'de, S> DeserializeSeed<'de> for CellSeeded<S>
+    impl<'de, KS, VS> DeserializeSeed<'de> for HashStorageSeeded<KS, VS>
     where
-        S: DeserializeSeed<'de> + Clone,
-        S::Value: Value,
+        KS: DeserializeSeed<'de> + Clone,
+        VS: DeserializeSeed<'de> + Clone,
+        KS::Value: HashKey,
+        VS::Value: Value,
     {
-        type Value = Cell<S::Value>;
+        type Value = HashStorage<KS::Value, VS::Value>;
 
         fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
         where
@@ -422,14 +1242,800 @@ mod cell {
                 }
             }
 
-            struct CellSeededVisitor<S> {
-                seed: S,
-            }
+            struct HashStorageSeededVisitor<KS, VS> {
+                kseed: KS,
+                vseed: VS,
+            }
+
+            impl<'de, KS, VS> Visitor<'de> for HashStorageSeededVisitor<KS, VS>
+            where
+                KS: DeserializeSeed<'de> + Clone,
+                VS: DeserializeSeed<'de> + Clone,
+                KS::Value: HashKey,
+                VS::Value: Value,
+            {
+                type Value = HashStorage<KS::Value, VS::Value>;
+
+                fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                    formatter.write_fmt(format_args!(
+                        "struct HashStorage<{}, {}>",
+                        core::any::type_name::<KS::Value>(),
+                        core::any::type_name::<VS::Value>(),
+                    ))
+                }
+
+                fn visit_seq<SA>(self, mut seq: SA) -> Result<Self::Value, SA::Error>
+                where
+                    SA: SeqAccess<'de>,
+                {
+                    let revert = seq
+                        .next_element_seed(RevertDeserializeSeeded {
+                            kseed: self.kseed.clone(),
+                            vseed: self.vseed.clone(),
+                        })?
+                        .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                    let blocks = seq
+                        .next_element_seed(BlocksDeserializeSeeded {
+                            kseed: self.kseed.clone(),
+                            vseed: self.vseed.clone(),
+                        })?
+                        .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                    Ok(HashStorage {
+                        revert,
+                        blocks,
+                        version: Version::new(0),
+                    })
+                }
+
+                fn visit_map<MA>(self, mut map: MA) -> Result<Self::Value, MA::Error>
+                where
+                    MA: MapAccess<'de>,
+                {
+                    let mut revert = None;
+                    let mut blocks = None;
+                    while let Some(key) = map.next_key()? {
+                        match key {
+                            Field::Revert => {
+                                if revert.is_some() {
+                                    return Err(de::Error::duplicate_field("revert"));
+                                }
+                                revert = Some(map.next_value_seed(RevertDeserializeSeeded {
+                                    kseed: self.kseed.clone(),
+                                    vseed: self.vseed.clone(),
+                                })?);
+                            }
+                            Field::Blocks => {
+                                if blocks.is_some() {
+                                    return Err(de::Error::duplicate_field("blocks"));
+                                }
+                                blocks = Some(map.next_value_seed(BlocksDeserializeSeeded {
+                                    kseed: self.kseed.clone(),
+                                    vseed: self.vseed.clone(),
+                                })?);
+                            }
+                        }
+                    }
+                    let revert = revert.ok_or_else(|| de::Error::missing_field("revert"))?;
+                    let blocks = blocks.ok_or_else(|| de::Error::missing_field("blocks"))?;
+                    Ok(HashStorage {
+                        revert,
+                        blocks,
+                        version: Version::new(0),
+                    })
+                }
+            }
+
+            const FIELDS: &[&str] = &["revert", "blocks"];
+            deserializer.deserialize_struct(
+                "HashStorage",
+                FIELDS,
+                HashStorageSeededVisitor {
+                    kseed: self.kseed,
+                    vseed: self.vseed,
+                },
+            )
+        }
+    }
+
+    struct BlocksDeserializeSeeded<KS, VS> {
+        kseed: KS,
+        vseed: VS,
+    }
+
+    impl<'de, KS, VS> DeserializeSeed<'de> for BlocksDeserializeSeeded<KS, VS>
+    where
+        KS: DeserializeSeed<'de> + Clone,
+        VS: DeserializeSeed<'de> + Clone,
+        KS::Value: HashKey,
+        VS::Value: Value,
+    {
+        type Value = concread::hashmap::HashMap<KS::Value, VS::Value>;
+
+        fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            struct BlocksSeededVisitor<KS, VS> {
+                kseed: KS,
+                vseed: VS,
+            }
+
+            impl<'de, KS, VS> Visitor<'de> for BlocksSeededVisitor<KS, VS>
+            where
+                KS: DeserializeSeed<'de> + Clone,
+                VS: DeserializeSeed<'de> + Clone,
+                KS::Value: HashKey,
+                VS::Value: Value,
+            {
+                type Value = concread::hashmap::HashMap<KS::Value, VS::Value>;
+
+                fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                    formatter.write_str("a map")
+                }
+
+                fn visit_map<MA>(self, mut map: MA) -> Result<Self::Value, MA::Error>
+                where
+                    MA: MapAccess<'de>,
+                {
+                    core::iter::from_fn(|| {
+                        map.next_entry_seed(self.kseed.clone(), self.vseed.clone())
+                            .transpose()
+                    })
+                    .collect::<Result<concread::hashmap::HashMap<_, _>, MA::Error>>()
+                }
+            }
+
+            deserializer.deserialize_map(BlocksSeededVisitor {
+                kseed: self.kseed,
+                vseed: self.vseed,
+            })
+        }
+    }
+
+    struct RevertDeserializeSeeded<KS, VS> {
+        kseed: KS,
+        vseed: VS,
+    }
+
+    impl<'de, KS, VS> DeserializeSeed<'de> for RevertDeserializeSeeded<KS, VS>
+    where
+        KS: DeserializeSeed<'de> + Clone,
+        VS: DeserializeSeed<'de> + Clone,
+        KS::Value: HashKey,
+        VS::Value: Value,
+    {
+        type Value = concread::ebrcell::EbrCell<StdHashMap<KS::Value, Option<VS::Value>>>;
+
+        fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            struct RevertSeededVisitor<KS, VS> {
+                kseed: KS,
+                vseed: VS,
+            }
+
+            impl<'de, KS, VS> Visitor<'de> for RevertSeededVisitor<KS, VS>
+            where
+                KS: DeserializeSeed<'de> + Clone,
+                VS: DeserializeSeed<'de> + Clone,
+                KS::Value: HashKey,
+                VS::Value: Value,
+            {
+                type Value = concread::ebrcell::EbrCell<StdHashMap<KS::Value, Option<VS::Value>>>;
+
+                fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                    formatter.write_str("a map")
+                }
+
+                fn visit_map<MA>(self, mut map: MA) -> Result<Self::Value, MA::Error>
+                where
+                    MA: MapAccess<'de>,
+                {
+                    core::iter::from_fn(|| {
+                        map.next_entry_seed(
+                            self.kseed.clone(),
+                            OptionSeeded {
+                                seed: self.vseed.clone(),
+                            },
+                        )
+                        .transpose()
+                    })
+                    .collect::<Result<StdHashMap<_, _>, MA::Error>>()
+                    .map(concread::EbrCell::new)
+                }
+            }
+
+            deserializer.deserialize_map(RevertSeededVisitor {
+                kseed: self.kseed,
+                vseed: self.vseed,
+            })
+        }
+    }
+}
+
+mod set {
+    use crate::{
+        set::StorageSet,
+        storage::{Quota, Redo, Storage},
+    };
+
+    use super::*;
+
+    /// Struct to deserialize [`StorageSet`] with provided seed for keys
+    pub struct StorageSetSeeded<S> {
+        pub seed: S,
+    }
+
+    /// Revert of the set is serialized as map from key to it's previous presence in the set
+    impl<K: Serialize + Key> Serialize for StorageSet<K> {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: serde::Serializer,
+        {
+            // Retry if commit is published while revert and blocks are opened, so that they are from the same commit
+            let (version, revertible, revert, redo, blocks) =
+                self.storage.version.read(|version| {
+                    (
+                        version,
+                        self.storage.revertible.load(Ordering::Relaxed),
+                        self.storage.revert.read(),
+                        self.storage.redo.read(),
+                        self.storage.blocks.read(),
+                    )
+                });
+            // Redo is kept only while the revert is the latest commit
+            let redoable = redo.version == Some(version);
+            let no_redo = BTreeMap::new();
+
+            let mut state = serializer.serialize_struct("StorageSet", 5)?;
+            state.serialize_field("revert", &RevertSerializeHelper(revert.deref()))?;
+            state.serialize_field("blocks", &BlocksSerializeHelper(blocks))?;
+            state.serialize_field("revertible", &revertible)?;
+            state.serialize_field("redoable", &redoable)?;
+            state.serialize_field(
+                "redo",
+                &RevertSerializeHelper(if redoable { &redo.changes } else { &no_redo }),
+            )?;
+            state.end()
+        }
+    }
+
+    struct RevertSerializeHelper<'revert, K>(&'revert BTreeMap<K, Option<()>>);
+
+    impl<K: Serialize + Key> Serialize for RevertSerializeHelper<'_, K> {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: serde::Serializer,
+        {
+            let mut map = serializer.serialize_map(Some(self.0.len()))?;
+            for (k, v) in self.0.iter() {
+                map.serialize_entry(k, &v.is_some())?;
+            }
+            map.end()
+        }
+    }
+
+    struct BlocksSerializeHelper<'block, K: Key>(concread::bptree::BptreeMapReadTxn<'block, K, ()>);
+
+    impl<K: Serialize + Key> Serialize for BlocksSerializeHelper<'_, K> {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: serde::Serializer,
+        {
+            let mut seq = serializer.serialize_seq(Some(self.0.len()))?;
+            for k in self.0.keys() {
+                seq.serialize_element(k)?;
+            }
+            seq.end()
+        }
+    }
+
+    impl<'de, K: Deserialize<'de> + Key> Deserialize<'de> for StorageSet<K> {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            StorageSetSeeded {
+                seed: core::marker::PhantomData::<K>,
+            }
+            .deserialize(deserializer)
+        }
+    }
+
+    impl<'de, S> DeserializeSeed<'de> for StorageSetSeeded<S>
+    where
+        S: DeserializeSeed<'de> + Clone,
+        S::Value: Key,
+    {
+        type Value = StorageSet<S::Value>;
+
+        fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            enum Field {
+                Revert,
+                Blocks,
+                Revertible,
+                Redoable,
+                Redo,
+            }
+
+            impl<'de> Deserialize<'de> for Field {
+                fn deserialize<D>(deserializer: D) -> Result<Field, D::Error>
+                where
+                    D: Deserializer<'de>,
+                {
+                    struct FieldVisitor;
+
+                    impl<'de> Visitor<'de> for FieldVisitor {
+                        type Value = Field;
+
+                        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                            formatter
+                                .write_str("`revert`, `blocks`, `revertible`, `redoable` or `redo`")
+                        }
+
+                        fn visit_str<E>(self, value: &str) -> Result<Field, E>
+                        where
+                            E: de::Error,
+                        {
+                            match value {
+                                "revert" => Ok(Field::Revert),
+                                "blocks" => Ok(Field::Blocks),
+                                "revertible" => Ok(Field::Revertible),
+                                "redoable" => Ok(Field::Redoable),
+                                "redo" => Ok(Field::Redo),
+                                _ => Err(de::Error::unknown_field(value, FIELDS)),
+                            }
+                        }
+                    }
+
+                    deserializer.deserialize_identifier(FieldVisitor)
+                }
+            }
+
+            struct StorageSetSeededVisitor<S> {
+                seed: S,
+            }
+
+            /// Build set from the decoded snapshot, which is loaded at version `0`
+            fn finish<K: Key>(
+                revertible: bool,
+                revert: BTreeMap<K, Option<()>>,
+                redo: Option<BTreeMap<K, Option<()>>>,
+                blocks: concread::bptree::BptreeMap<K, ()>,
+            ) -> StorageSet<K> {
+                let redo = redo.map_or_else(Redo::default, |changes| Redo {
+                    version: Some(0),
+                    changes,
+                });
+                StorageSet {
+                    storage: Storage {
+                        revert: concread::EbrCell::new(revert),
+                        blocks,
+                        indexes: Vec::new(),
+                        version: Version::new(0),
+                        modified: None,
+                        tracked_since: AtomicU64::new(0),
+                        redo: concread::EbrCell::new(redo),
+                        revertible: AtomicBool::new(revertible),
+                        receipts: concread::EbrCell::new(Vec::new()),
+                        meta: concread::EbrCell::new(None),
+                        quota: Quota::new(),
+                        bytes: AtomicUsize::new(0),
+                    },
+                }
+            }
+
+            impl<'de, S> Visitor<'de> for StorageSetSeededVisitor<S>
+            where
+                S: DeserializeSeed<'de> + Clone,
+                S::Value: Key,
+            {
+                type Value = StorageSet<S::Value>;
+
+                fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                    formatter.write_fmt(format_args!(
+                        "struct StorageSet<{}>",
+                        core::any::type_name::<S::Value>(),
+                    ))
+                }
+
+                fn visit_seq<SA>(self, mut seq: SA) -> Result<Self::Value, SA::Error>
+                where
+                    SA: SeqAccess<'de>,
+                {
+                    let revert = seq
+                        .next_element_seed(RevertDeserializeSeeded {
+                            seed: self.seed.clone(),
+                        })?
+                        .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                    let blocks = seq
+                        .next_element_seed(BlocksDeserializeSeeded {
+                            seed: self.seed.clone(),
+                        })?
+                        .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                    // Snapshots written before they were recorded are neither reverted nor redone
+                    let revertible = seq.next_element()?.unwrap_or(false);
+                    let redoable = seq.next_element()?.unwrap_or(false);
+                    let redo = seq.next_element_seed(RevertDeserializeSeeded {
+                        seed: self.seed.clone(),
+                    })?;
+                    Ok(finish(
+                        revertible,
+                        revert,
+                        redo.filter(|_| redoable),
+                        blocks,
+                    ))
+                }
+
+                fn visit_map<MA>(self, mut map: MA) -> Result<Self::Value, MA::Error>
+                where
+                    MA: MapAccess<'de>,
+                {
+                    let mut revert = None;
+                    let mut blocks = None;
+                    let mut revertible = None;
+                    let mut redoable = None;
+                    let mut redo = None;
+                    while let Some(key) = map.next_key()? {
+                        match key {
+                            Field::Revert => {
+                                if revert.is_some() {
+                                    return Err(de::Error::duplicate_field("revert"));
+                                }
+                                revert = Some(map.next_value_seed(RevertDeserializeSeeded {
+                                    seed: self.seed.clone(),
+                                })?);
+                            }
+                            Field::Blocks => {
+                                if blocks.is_some() {
+                                    return Err(de::Error::duplicate_field("blocks"));
+                                }
+                                blocks = Some(map.next_value_seed(BlocksDeserializeSeeded {
+                                    seed: self.seed.clone(),
+                                })?);
+                            }
+                            Field::Revertible => {
+                                if revertible.is_some() {
+                                    return Err(de::Error::duplicate_field("revertible"));
+                                }
+                                revertible = Some(map.next_value()?);
+                            }
+                            Field::Redoable => {
+                                if redoable.is_some() {
+                                    return Err(de::Error::duplicate_field("redoable"));
+                                }
+                                redoable = Some(map.next_value()?);
+                            }
+                            Field::Redo => {
+                                if redo.is_some() {
+                                    return Err(de::Error::duplicate_field("redo"));
+                                }
+                                redo = Some(map.next_value_seed(RevertDeserializeSeeded {
+                                    seed: self.seed.clone(),
+                                })?);
+                            }
+                        }
+                    }
+                    let revert = revert.ok_or_else(|| de::Error::missing_field("revert"))?;
+                    let blocks = blocks.ok_or_else(|| de::Error::missing_field("blocks"))?;
+                    // Snapshots written before they were recorded are neither reverted nor redone
+                    let redo = redoable.unwrap_or(false).then(|| redo.unwrap_or_default());
+                    Ok(finish(revertible.unwrap_or(false), revert, redo, blocks))
+                }
+            }
+
+            const FIELDS: &[&str] = &["revert", "blocks", "revertible", "redoable", "redo"];
+            deserializer.deserialize_struct(
+                "StorageSet",
+                FIELDS,
+                StorageSetSeededVisitor { seed: self.seed },
+            )
+        }
+    }
+
+    struct BlocksDeserializeSeeded<S> {
+        seed: S,
+    }
+
+    impl<'de, S> DeserializeSeed<'de> for BlocksDeserializeSeeded<S>
+    where
+        S: DeserializeSeed<'de> + Clone,
+        S::Value: Key,
+    {
+        type Value = concread::bptree::BptreeMap<S::Value, ()>;
+
+        fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            struct BlocksSeededVisitor<S> {
+                seed: S,
+            }
+
+            impl<'de, S> Visitor<'de> for BlocksSeededVisitor<S>
+            where
+                S: DeserializeSeed<'de> + Clone,
+                S::Value: Key,
+            {
+                type Value = concread::bptree::BptreeMap<S::Value, ()>;
+
+                fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                    formatter.write_str("a sequence")
+                }
+
+                fn visit_seq<SA>(self, mut seq: SA) -> Result<Self::Value, SA::Error>
+                where
+                    SA: SeqAccess<'de>,
+                {
+                    core::iter::from_fn(|| {
+                        seq.next_element_seed(self.seed.clone())
+                            .map(|key| key.map(|key| (key, ())))
+                            .transpose()
+                    })
+                    .collect::<Result<concread::bptree::BptreeMap<_, _>, SA::Error>>()
+                }
+            }
+
+            deserializer.deserialize_seq(BlocksSeededVisitor { seed: self.seed })
+        }
+    }
+
+    struct RevertDeserializeSeeded<S> {
+        seed: S,
+    }
+
+    impl<'de, S> DeserializeSeed<'de> for RevertDeserializeSeeded<S>
+    where
+        S: DeserializeSeed<'de> + Clone,
+        S::Value: Key,
+    {
+        type Value = BTreeMap<S::Value, Option<()>>;
+
+        fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            struct RevertSeededVisitor<S> {
+                seed: S,
+            }
+
+            impl<'de, S> Visitor<'de> for RevertSeededVisitor<S>
+            where
+                S: DeserializeSeed<'de> + Clone,
+                S::Value: Key,
+            {
+                type Value = BTreeMap<S::Value, Option<()>>;
+
+                fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                    formatter.write_str("a map")
+                }
+
+                fn visit_map<MA>(self, mut map: MA) -> Result<Self::Value, MA::Error>
+                where
+                    MA: MapAccess<'de>,
+                {
+                    core::iter::from_fn(|| {
+                        map.next_entry_seed(self.seed.clone(), core::marker::PhantomData::<bool>)
+                            .map(|entry| entry.map(|(key, present)| (key, present.then_some(()))))
+                            .transpose()
+                    })
+                    .collect::<Result<BTreeMap<_, _>, MA::Error>>()
+                }
+            }
+
+            deserializer.deserialize_map(RevertSeededVisitor { seed: self.seed })
+        }
+    }
+}
+
+mod cell {
+    use concread::EbrCell;
+
+    use crate::{
+        cell::{Cell, View},
+        storage::Redo,
+    };
+
+    use super::*;
+
+    /// Struct to deserialize [`Storage`] with provided seed for keys and values
+    /// In case seed is only required for keys or values use [`PhantomData`] in place where seed is not required.
+    pub struct CellSeeded<S> {
+        pub seed: S,
+    }
+
+    impl<V: Serialize + Value> Serialize for Cell<V> {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: serde::Serializer,
+        {
+            // Retry if commit is published while revert and blocks are opened, so that they are from the same commit
+            let (version, revertible, revert, redo, blocks) = self.version.read(|version| {
+                (
+                    version,
+                    self.revertible.load(Ordering::Relaxed),
+                    self.revert.read(),
+                    self.redo.read(),
+                    self.blocks.read(),
+                )
+            });
+            // Redo is kept only while the revert is the latest commit
+            let redoable = redo.version == Some(version);
+
+            let mut state = serializer.serialize_struct("Storage", 5)?;
+            state.serialize_field("revert", revert.deref())?;
+            state.serialize_field("blocks", blocks.deref())?;
+            state.serialize_field("revertible", &revertible)?;
+            state.serialize_field("redoable", &redoable)?;
+            state.serialize_field("redo", &redo.changes.as_ref().filter(|_| redoable))?;
+            state.end()
+        }
+    }
+
+    /// Serialize value of the view, revert is not included
+    impl<V: Serialize + Value> Serialize for View<'_, V> {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: serde::Serializer,
+        {
+            self.get().serialize(serializer)
+        }
+    }
+
+    impl<'de, V: Deserialize<'de> + Value> Deserialize<'de> for Cell<V> {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            CellSeeded {
+                seed: core::marker::PhantomData::<V>,
+            }
+            .deserialize(deserializer)
+        }
+    }
+
+    /// Struct to deserialize [`Cell`] validating loaded value, created by [`CellSeeded::with_validation`]
+    pub struct ValidatingCellSeeded<S, C> {
+        pub seed: S,
+        pub validation: C,
+    }
+
+    impl<S> CellSeeded<S> {
+        /// Reject snapshots which fail `validation`, entries are checked with `()` key
+        pub fn with_validation<C>(self, validation: C) -> ValidatingCellSeeded<S, C> {
+            ValidatingCellSeeded {
+                seed: self.seed,
+                validation,
+            }
+        }
+    }
+
+    impl<'de, S> DeserializeSeed<'de> for CellSeeded<S>
+    where
+        S: DeserializeSeed<'de> + Clone,
+        S::Value: Value,
+    {
+        type Value = Cell<S::Value>;
+
+        fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            self.with_validation(NoValidation).deserialize(deserializer)
+        }
+    }
+
+    impl<'de, S, C> DeserializeSeed<'de> for ValidatingCellSeeded<S, C>
+    where
+        S: DeserializeSeed<'de> + Clone,
+        S::Value: Value,
+        C: Validation<(), S::Value>,
+    {
+        type Value = Cell<S::Value>;
+
+        fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            enum Field {
+                Revert,
+                Blocks,
+                Revertible,
+                Redoable,
+                Redo,
+            }
+
+            impl<'de> Deserialize<'de> for Field {
+                fn deserialize<D>(deserializer: D) -> Result<Field, D::Error>
+                where
+                    D: Deserializer<'de>,
+                {
+                    struct FieldVisitor;
+
+                    impl<'de> Visitor<'de> for FieldVisitor {
+                        type Value = Field;
+
+                        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                            formatter
+                                .write_str("`revert`, `blocks`, `revertible`, `redoable` or `redo`")
+                        }
+
+                        fn visit_str<E>(self, value: &str) -> Result<Field, E>
+                        where
+                            E: de::Error,
+                        {
+                            match value {
+                                "revert" => Ok(Field::Revert),
+                                "blocks" => Ok(Field::Blocks),
+                                "revertible" => Ok(Field::Revertible),
+                                "redoable" => Ok(Field::Redoable),
+                                "redo" => Ok(Field::Redo),
+                                _ => Err(de::Error::unknown_field(value, FIELDS)),
+                            }
+                        }
+                    }
+
+                    deserializer.deserialize_identifier(FieldVisitor)
+                }
+            }
+
+            struct CellSeededVisitor<S, C> {
+                seed: S,
+                validation: C,
+            }
+
+            impl<S, C> CellSeededVisitor<S, C> {
+                /// Validate loaded value and it's previous and reverted versions
+                fn finish<V: Value, E: de::Error>(
+                    self,
+                    revertible: bool,
+                    revert: Option<V>,
+                    redo: Option<Option<V>>,
+                    blocks: V,
+                ) -> Result<Cell<V>, E>
+                where
+                    C: Validation<(), V>,
+                {
+                    if C::ENABLED {
+                        self.validation.entry(&(), &blocks).map_err(|reason| {
+                            de::Error::custom(format_args!("invalid `blocks`: {reason}"))
+                        })?;
+                        self.validation
+                            .revert(&(), revert.as_ref(), Some(&blocks))
+                            .map_err(|reason| {
+                                de::Error::custom(format_args!("invalid `revert`: {reason}"))
+                            })?;
+                        if let Some(redo) = &redo {
+                            self.validation
+                                .revert(&(), redo.as_ref(), Some(&blocks))
+                                .map_err(|reason| {
+                                    de::Error::custom(format_args!("invalid `redo`: {reason}"))
+                                })?;
+                        }
+                    }
+                    let redo = redo.map_or_else(Redo::default, |changes| Redo {
+                        version: Some(0),
+                        changes,
+                    });
+                    Ok(Cell {
+                        revertible: AtomicBool::new(revertible),
+                        revert: EbrCell::new(revert),
+                        blocks: EbrCell::new(blocks),
+                        version: Version::new(0),
+                        redo: EbrCell::new(redo),
+                    })
+                }
+            }
 
-            impl<'de, S> Visitor<'de> for CellSeededVisitor<S>
+            impl<'de, S, C> Visitor<'de> for CellSeededVisitor<S, C>
             where
                 S: DeserializeSeed<'de> + Clone,
                 S::Value: Value,
+                C: Validation<(), S::Value>,
             {
                 type Value = Cell<S::Value>;
 
@@ -452,10 +2058,13 @@ mod cell {
                     let blocks = seq
                         .next_element_seed(self.seed.clone())?
                         .ok_or_else(|| de::Error::invalid_length(1, &self))?;
-                    Ok(Cell {
-                        revert: EbrCell::new(revert),
-                        blocks: EbrCell::new(blocks),
-                    })
+                    // Snapshots written before they were recorded are neither reverted nor redone
+                    let revertible = seq.next_element()?.unwrap_or(false);
+                    let redoable = seq.next_element()?.unwrap_or(false);
+                    let redo = seq.next_element_seed(OptionSeeded {
+                        seed: self.seed.clone(),
+                    })?;
+                    self.finish(revertible, revert, redo.filter(|_| redoable), blocks)
                 }
 
                 fn visit_map<MA>(self, mut map: MA) -> Result<Self::Value, MA::Error>
@@ -464,6 +2073,9 @@ mod cell {
                 {
                     let mut revert = None;
                     let mut blocks = None;
+                    let mut revertible = None;
+                    let mut redoable = None;
+                    let mut redo = None;
                     while let Some(key) = map.next_key()? {
                         match key {
                             Field::Revert => {
@@ -480,19 +2092,45 @@ mod cell {
                                 }
                                 blocks = Some(map.next_value_seed(self.seed.clone())?);
                             }
+                            Field::Revertible => {
+                                if revertible.is_some() {
+                                    return Err(de::Error::duplicate_field("revertible"));
+                                }
+                                revertible = Some(map.next_value()?);
+                            }
+                            Field::Redoable => {
+                                if redoable.is_some() {
+                                    return Err(de::Error::duplicate_field("redoable"));
+                                }
+                                redoable = Some(map.next_value()?);
+                            }
+                            Field::Redo => {
+                                if redo.is_some() {
+                                    return Err(de::Error::duplicate_field("redo"));
+                                }
+                                redo = Some(map.next_value_seed(OptionSeeded {
+                                    seed: self.seed.clone(),
+                                })?);
+                            }
                         }
                     }
                     let revert = revert.ok_or_else(|| de::Error::missing_field("revert"))?;
                     let blocks = blocks.ok_or_else(|| de::Error::missing_field("blocks"))?;
-                    Ok(Cell {
-                        revert: EbrCell::new(revert),
-                        blocks: EbrCell::new(blocks),
-                    })
+                    // Snapshots written before they were recorded are neither reverted nor redone
+                    let redo = redoable.unwrap_or(false).then(|| redo.flatten());
+                    self.finish(revertible.unwrap_or(false), revert, redo, blocks)
                 }
             }
 
-            const FIELDS: &[&str] = &["revert", "blocks"];
-            deserializer.deserialize_struct("Cell", FIELDS, CellSeededVisitor { seed: self.seed })
+            const FIELDS: &[&str] = &["revert", "blocks", "revertible", "redoable", "redo"];
+            deserializer.deserialize_struct(
+                "Cell",
+                FIELDS,
+                CellSeededVisitor {
+                    seed: self.seed,
+                    validation: self.validation,
+                },
+            )
         }
     }
 }
@@ -548,7 +2186,9 @@ where
 mod tests {
     use crate::{
         cell::Cell,
-        storage::{Storage, StorageReadOnly},
+        hash_storage::{HashStorage, HashStorageReadOnly},
+        set::{StorageSet, StorageSetReadOnly},
+        storage::{Reverted, Storage, StorageReadOnly},
     };
 
     #[test]
@@ -573,6 +2213,65 @@ mod tests {
         }
     }
 
+    #[test]
+    fn serialize_deserialize_hash_storage() {
+        let storage = HashStorage::<u64, u64>::new();
+
+        for i in 0..100 {
+            let mut block = storage.block();
+            block.insert(i, i);
+            block.commit();
+        }
+
+        let storage: HashStorage<u64, u64> = serde_json::from_str(
+            &serde_json::to_string(&storage).expect("failed to serialize storage"),
+        )
+        .expect("failed to deserialize storage");
+
+        let view = storage.view();
+        for i in 0..100 {
+            let value = view.get(&i);
+            assert_eq!(value, Some(&i));
+        }
+
+        storage.block_and_revert().commit();
+
+        let view = storage.view();
+        assert_eq!(view.get(&99), None);
+        assert_eq!(view.len(), 99);
+    }
+
+    #[test]
+    fn serialize_deserialize_set() {
+        let set = StorageSet::<u64>::new();
+
+        {
+            let mut block = set.block();
+            block.insert(0);
+            block.insert(1);
+            block.commit();
+        }
+
+        {
+            let mut block = set.block();
+            block.remove(0);
+            block.insert(2);
+            block.commit();
+        }
+
+        let set: StorageSet<u64> =
+            serde_json::from_str(&serde_json::to_string(&set).expect("failed to serialize set"))
+                .expect("failed to deserialize set");
+
+        let view = set.view();
+        assert!(view.iter().copied().eq([1, 2]));
+
+        set.block_and_revert().commit();
+
+        let view = set.view();
+        assert!(view.iter().copied().eq([0, 1]));
+    }
+
     #[test]
     fn serialize_deserialize_cell() {
         let cell = Cell::new(0_u64);
@@ -599,4 +2298,445 @@ mod tests {
         let view = cell.view();
         assert_eq!(view.get(), &0);
     }
+
+    #[test]
+    fn serialize_keeps_revertible() {
+        fn reload<T: serde::Serialize + serde::de::DeserializeOwned>(value: &T) -> T {
+            serde_json::from_str(&serde_json::to_string(value).expect("failed to serialize"))
+                .expect("failed to deserialize")
+        }
+
+        // Revert block writes revert, but can't be reverted itself
+        let storage = Storage::<u64, u64>::new();
+        storage.block().commit();
+        {
+            let mut block = storage.block_and_revert();
+            block.insert(0, 0);
+            block.commit();
+        }
+        let loaded = reload(&storage);
+        assert_eq!(storage.block_and_revert().reverted(), Reverted::Nothing);
+        assert_eq!(loaded.block_and_revert().reverted(), Reverted::Nothing);
+        assert_eq!(loaded.view().get(&0), Some(&0));
+
+        // Block without writes can be reverted although it's revert is empty
+        let storage = Storage::<u64, u64>::new();
+        storage.block().commit();
+        let loaded = reload(&storage);
+        let expected = Reverted::Block {
+            version: 1,
+            keys: 0,
+        };
+        assert_eq!(storage.block_and_revert().reverted(), expected);
+        assert_eq!(loaded.block_and_revert().reverted(), expected);
+
+        let cell = Cell::new(0_u64);
+        cell.block().commit();
+        {
+            let mut block = cell.block_and_revert();
+            *block.get_mut() = 1;
+            block.commit();
+        }
+        let loaded = reload(&cell);
+        assert_eq!(loaded.block_and_revert().reverted(), Reverted::Nothing);
+        assert_eq!(loaded.view().get(), &1);
+    }
+
+    #[test]
+    fn serialize_keeps_redo() {
+        fn reload<T: serde::Serialize + serde::de::DeserializeOwned>(value: &T) -> T {
+            serde_json::from_str(&serde_json::to_string(value).expect("failed to serialize"))
+                .expect("failed to deserialize")
+        }
+
+        let storage = Storage::<u64, u64>::new();
+        {
+            let mut block = storage.block();
+            block.insert(0, 0);
+            block.commit();
+        }
+        storage.block_and_revert().commit();
+        let loaded = reload(&storage);
+        assert_eq!(loaded.view().get(&0), None);
+        loaded
+            .block_and_redo()
+            .expect("revert is the latest block")
+            .commit();
+        assert_eq!(loaded.view().get(&0), Some(&0));
+
+        // Redo is dropped once blocks are committed after the revert
+        storage.block().commit();
+        assert!(reload(&storage).block_and_redo().is_err());
+
+        let set = StorageSet::<u64>::new();
+        {
+            let mut block = set.block();
+            block.insert(0);
+            block.commit();
+        }
+        set.block_and_revert().commit();
+        let loaded = reload(&set);
+        assert!(!loaded.view().contains(&0));
+        loaded
+            .storage
+            .block_and_redo()
+            .expect("revert is the latest block")
+            .commit();
+        assert!(loaded.view().contains(&0));
+
+        let cell = Cell::new(0_u64);
+        {
+            let mut block = cell.block();
+            *block.get_mut() = 1;
+            block.commit();
+        }
+        cell.block_and_revert().commit();
+        let loaded = reload(&cell);
+        assert_eq!(loaded.view().get(), &0);
+        loaded
+            .block_and_redo()
+            .expect("revert is the latest block")
+            .commit();
+        assert_eq!(loaded.view().get(), &1);
+    }
+
+    #[test]
+    fn serialize_consistent_with_concurrent_commits() {
+        const BLOCKS: u64 = 1_000;
+
+        let storage = Storage::<u64, u64>::from_iter([(0, 0)]);
+        let hash_storage = HashStorage::<u64, u64>::from_iter([(0, 0)]);
+        let cell = Cell::new(0_u64);
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for i in 1..=BLOCKS {
+                    let mut block = storage.block();
+                    block.insert(0, i);
+                    block.commit();
+
+                    let mut block = hash_storage.block();
+                    block.insert(0, i);
+                    block.commit();
+
+                    let mut block = cell.block();
+                    *block.get_mut() = i;
+                    block.commit();
+                }
+            });
+
+            // Every block increments value by one, so revert must hold previous value of the blocks
+            loop {
+                let json = serde_json::to_value(&storage).expect("failed to serialize storage");
+                let value = json["blocks"]["0"].as_u64().expect("key is present");
+                if let Some(prev) = json["revert"]["0"].as_u64() {
+                    assert_eq!(prev + 1, value);
+                }
+
+                let json =
+                    serde_json::to_value(&hash_storage).expect("failed to serialize hash storage");
+                let value = json["blocks"]["0"].as_u64().expect("key is present");
+                if let Some(prev) = json["revert"]["0"].as_u64() {
+                    assert_eq!(prev + 1, value);
+                }
+
+                let json = serde_json::to_value(&cell).expect("failed to serialize cell");
+                let value = json["blocks"].as_u64().expect("value is present");
+                if let Some(prev) = json["revert"].as_u64() {
+                    assert_eq!(prev + 1, value);
+                }
+
+                if value == BLOCKS {
+                    break;
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn commit_during_serialization() {
+        static STORAGE: std::sync::OnceLock<Storage<u64, Probe>> = std::sync::OnceLock::new();
+
+        /// Value which waits for the commit to the storage while being serialized
+        #[derive(Clone)]
+        struct Probe;
+
+        impl serde::Serialize for Probe {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let storage = STORAGE.get().expect("storage is initialized");
+                std::thread::scope(|s| s.spawn(|| storage.block().commit()).join())
+                    .expect("commit succeeded");
+                serializer.serialize_unit()
+            }
+        }
+
+        impl super::TypeTag for Probe {
+            fn tag() -> std::borrow::Cow<'static, str> {
+                "Probe".into()
+            }
+        }
+
+        let storage = STORAGE.get_or_init(|| Storage::from_iter([(0, Probe)]));
+        // Serialization doesn't block commits, so probe doesn't deadlock
+        let json = serde_json::to_value(storage).expect("failed to serialize storage");
+        assert_eq!(json["version"], 0);
+        assert_eq!(storage.version(), 1);
+    }
+
+    #[test]
+    fn serialize_view() {
+        let storage = Storage::<u64, u64>::from_iter([(0, 0), (1, 1)]);
+        let cell = Cell::new(0_u64);
+
+        let storage_view = storage.view();
+        let cell_view = cell.view();
+
+        {
+            let mut block = storage.block();
+            block.insert(2, 2);
+            block.commit();
+
+            let mut block = cell.block();
+            *block.get_mut() = 1;
+            block.commit();
+        }
+
+        // View is serialized at the version it was created at without revert
+        assert_eq!(
+            serde_json::to_string(&storage_view).expect("failed to serialize view"),
+            r#"{"0":0,"1":1}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&cell_view).expect("failed to serialize view"),
+            "0"
+        );
+
+        // Snapshot of the view can be loaded as storage
+        let loaded: Storage<u64, u64> = serde_json::from_str(&format!(
+            r#"{{"revert":{{}},"blocks":{}}}"#,
+            serde_json::to_string(&storage.view()).expect("failed to serialize view")
+        ))
+        .expect("failed to deserialize storage");
+        assert!(loaded.view().iter().eq(storage.view().iter()));
+    }
+
+    #[test]
+    fn compound_type_tags() {
+        use super::TypeTag;
+
+        assert_eq!(<Vec<u64>>::tag(), "Vec<u64>");
+        assert_eq!(<Option<[u8; 32]>>::tag(), "Option<[u8; 32]>");
+        assert_eq!(<(u64,)>::tag(), "(u64,)");
+        assert_eq!(
+            <std::collections::BTreeMap<String, (u32, bool)>>::tag(),
+            "BTreeMap<String, (u32, bool)>"
+        );
+
+        let storage = Storage::<u64, Vec<(u64, String)>>::from_iter([
+            (0, vec![]),
+            (1, vec![(1, "1".to_owned()), (2, "2".to_owned())]),
+        ]);
+        let json = serde_json::to_string(&storage).expect("failed to serialize storage");
+        assert!(json.contains(r#""value_type":"Vec<(u64, String)>""#));
+
+        let loaded: Storage<u64, Vec<(u64, String)>> =
+            serde_json::from_str(&json).expect("failed to deserialize storage");
+        assert!(loaded.view().iter().eq(storage.view().iter()));
+        // Tag of the value type differs, so snapshot is rejected
+        assert!(serde_json::from_str::<Storage<u64, Vec<u64>>>(&json).is_err());
+    }
+
+    #[test]
+    fn snapshot_header() {
+        let storage = Storage::<u64, u64>::new();
+        for i in 0..3 {
+            let mut block = storage.block();
+            block.insert(i, i);
+            block.commit_with(i);
+        }
+
+        let json = serde_json::to_value(&storage).expect("failed to serialize storage");
+        assert_eq!(json["magic"], super::MAGIC);
+        assert_eq!(json["format"], super::FORMAT_VERSION);
+        assert_eq!(json["key_type"], "u64");
+
+        // Header must precede the entries, so snapshot is loaded from the text which keeps field order
+        let load = |json: serde_json::Value| {
+            let mut fields = json.as_object().expect("storage is a struct").clone();
+            let text = [
+                "magic",
+                "format",
+                "version",
+                "key_type",
+                "value_type",
+                "meta",
+                "revertible",
+                "redoable",
+                "revert",
+                "redo",
+                "blocks",
+            ]
+            .into_iter()
+            .map(|field| {
+                format!(
+                    "{:?}:{}",
+                    field,
+                    fields.remove(field).expect("field exists")
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+            format!("{{{text}}}")
+        };
+        let loaded: Storage<u64, u64> =
+            serde_json::from_str(&load(json.clone())).expect("failed to deserialize storage");
+        assert_eq!(loaded.version(), 3);
+        // Metadata of the latest block is kept
+        assert_eq!(loaded.view().meta::<u64>(), Some(&2));
+
+        // Snapshot of different types is rejected
+        assert!(serde_json::from_str::<Storage<u64, i64>>(&load(json.clone())).is_err());
+
+        // Snapshot with wrong magic or newer format is rejected
+        let mut corrupted = json.clone();
+        corrupted["magic"] = "garbage".into();
+        assert!(serde_json::from_str::<Storage<u64, u64>>(&load(corrupted)).is_err());
+        let mut newer = json.clone();
+        newer["format"] = (super::FORMAT_VERSION + 1).into();
+        assert!(serde_json::from_str::<Storage<u64, u64>>(&load(newer)).is_err());
+
+        // Entries can't be decoded before the header
+        assert!(serde_json::from_value::<Storage<u64, u64>>(json).is_err());
+    }
+
+    #[test]
+    fn snapshot_migration() {
+        use serde::de::DeserializeSeed;
+
+        use super::{Header, Migration, StorageSeeded};
+
+        // Unframed snapshot is loaded as is
+        let legacy = r#"{"revert":{"0":null},"blocks":{"0":1}}"#;
+        let storage: Storage<u64, u64> =
+            serde_json::from_str(legacy).expect("failed to deserialize storage");
+        assert_eq!(storage.view().get(&0), Some(&1));
+
+        /// Values of the unframed snapshots were stored as strings
+        #[derive(Clone)]
+        struct ParseSeed;
+
+        impl<'de> DeserializeSeed<'de> for ParseSeed {
+            type Value = u64;
+
+            fn deserialize<D: serde::Deserializer<'de>>(
+                self,
+                deserializer: D,
+            ) -> Result<u64, D::Error> {
+                <String as serde::Deserialize>::deserialize(deserializer)?
+                    .parse()
+                    .map_err(serde::de::Error::custom)
+            }
+        }
+
+        struct ParseLegacy;
+
+        impl<'de> Migration<'de, u64, u64> for ParseLegacy {
+            type KeySeed = core::marker::PhantomData<u64>;
+            type ValueSeed = ParseSeed;
+
+            fn seeds(
+                &self,
+                header: &Header,
+            ) -> Result<Option<(Self::KeySeed, Self::ValueSeed)>, String> {
+                match header.format {
+                    0 => Ok(Some((core::marker::PhantomData, ParseSeed))),
+                    format => Err(format!("unsupported format {format}")),
+                }
+            }
+        }
+
+        // Migration decodes values stored with the old schema
+        let strings = r#"{"revert":{"0":null},"blocks":{"0":"1"}}"#;
+        assert!(serde_json::from_str::<Storage<u64, u64>>(strings).is_err());
+        let storage = StorageSeeded {
+            kseed: core::marker::PhantomData::<u64>,
+            vseed: core::marker::PhantomData::<u64>,
+        }
+        .with_migration(ParseLegacy)
+        .deserialize(&mut serde_json::Deserializer::from_str(strings))
+        .expect("failed to deserialize storage");
+        assert_eq!(storage.view().get(&0), Some(&1));
+
+        // Migration errors are reported
+        let json = serde_json::to_string(&Storage::<u64, String>::from_iter([(0, "1".to_owned())]))
+            .expect("failed to serialize storage");
+        let result = StorageSeeded {
+            kseed: core::marker::PhantomData::<u64>,
+            vseed: core::marker::PhantomData::<u64>,
+        }
+        .with_migration(ParseLegacy)
+        .deserialize(&mut serde_json::Deserializer::from_str(&json));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn validation() {
+        use serde::de::DeserializeSeed;
+
+        use super::{CellSeeded, StorageSeeded, Structural};
+
+        fn load<C: super::Validation<u64, u64>>(json: &str, validation: C) -> Result<(), String> {
+            StorageSeeded {
+                kseed: core::marker::PhantomData::<u64>,
+                vseed: core::marker::PhantomData::<u64>,
+            }
+            .with_validation(validation)
+            .deserialize(&mut serde_json::Deserializer::from_str(json))
+            .map(drop)
+            .map_err(|error| error.to_string())
+        }
+
+        let duplicate = r#"{"revert":{},"blocks":{"0":1,"0":2}}"#;
+        let unordered = r#"{"revert":{"1":null,"0":null},"blocks":{"0":1}}"#;
+        // Without validation snapshots are accepted as is
+        assert!(serde_json::from_str::<Storage<u64, u64>>(duplicate).is_ok());
+        assert!(serde_json::from_str::<Storage<u64, u64>>(unordered).is_ok());
+        let error = load(duplicate, Structural).expect_err("duplicate key");
+        assert!(error.to_string().contains("duplicate key 0 in `blocks`"));
+        let error = load(unordered, Structural).expect_err("unordered keys");
+        assert!(error
+            .to_string()
+            .contains("key 0 in `revert` is out of order"));
+
+        // Predicate is applied to entries and their previous versions
+        let small = |_: &u64, value: &u64| {
+            if *value < 10 {
+                Ok(())
+            } else {
+                Err(format!("value {value} is too large"))
+            }
+        };
+        assert!(load(r#"{"revert":{"0":1},"blocks":{"0":2}}"#, small).is_ok());
+        let error =
+            load(r#"{"revert":{},"blocks":{"0":1,"1":10}}"#, small).expect_err("large value");
+        assert!(error.to_string().contains("invalid entry 1 in `blocks`"));
+        let error =
+            load(r#"{"revert":{"0":10},"blocks":{"0":1}}"#, small).expect_err("large value");
+        assert!(error.to_string().contains("invalid entry 0 in `revert`"));
+
+        let cell = CellSeeded {
+            seed: core::marker::PhantomData::<u64>,
+        }
+        .with_validation(|_: &(), value: &u64| {
+            if *value < 10 {
+                Ok(())
+            } else {
+                Err("too large".to_owned())
+            }
+        })
+        .deserialize(&mut serde_json::Deserializer::from_str(
+            r#"{"revert":10,"blocks":1}"#,
+        ));
+        assert!(cell.is_err());
+    }
 }
@@ -0,0 +1,413 @@
+//! Multi-version ordered set
+
+use std::{borrow::Borrow, cmp::Ordering, iter::Peekable, ops::RangeBounds};
+
+use crate::{
+    storage::{self, Storage, StorageReadOnly},
+    Key,
+};
+
+/// Multi-version ordered set of keys
+pub struct StorageSet<K: Key> {
+    pub(crate) storage: Storage<K, ()>,
+}
+
+impl<K: Key> StorageSet<K> {
+    /// Construct new [`Self`]
+    pub fn new() -> Self {
+        Self {
+            storage: Storage::new(),
+        }
+    }
+
+    /// Create persistent view of set at certain point in time
+    pub fn view(&self) -> View<'_, K> {
+        View {
+            view: self.storage.view(),
+        }
+    }
+
+    /// Create block to aggregate updates
+    pub fn block(&self) -> Block<'_, K> {
+        Block {
+            block: self.storage.block(),
+        }
+    }
+
+    /// Create block to aggregate updates and revert changes created in the latest block
+    pub fn block_and_revert(&self) -> Block<'_, K> {
+        Block {
+            block: self.storage.block_and_revert(),
+        }
+    }
+}
+
+impl<K: Key> Default for StorageSet<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Key> FromIterator<K> for StorageSet<K> {
+    fn from_iter<I: IntoIterator<Item = K>>(iter: I) -> Self {
+        Self {
+            storage: iter.into_iter().map(|key| (key, ())).collect(),
+        }
+    }
+}
+
+pub trait StorageSetReadOnly<K: Key> {
+    /// Check if key is present in the set
+    fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized;
+
+    /// Iterate over all keys in the set
+    fn iter(&self) -> Iter<'_, K>;
+
+    /// Iterate over range of keys in the set
+    fn range<Q>(&self, bounds: impl RangeBounds<Q>) -> RangeIter<'_, K>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized;
+
+    /// Get amount of keys in the set
+    fn len(&self) -> usize;
+
+    /// Check if set has no keys
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Implement [`StorageSetReadOnly`] by delegating to the underlying storage type
+macro_rules! impl_set_read_only {
+    ($ty:ty, $field:ident) => {
+        impl<K: Key> StorageSetReadOnly<K> for $ty {
+            fn contains<Q>(&self, key: &Q) -> bool
+            where
+                K: Borrow<Q>,
+                Q: Ord + ?Sized,
+            {
+                self.$field.get(key).is_some()
+            }
+
+            fn iter(&self) -> Iter<'_, K> {
+                Iter {
+                    iter: self.$field.iter(),
+                }
+            }
+
+            fn range<Q>(&self, bounds: impl RangeBounds<Q>) -> RangeIter<'_, K>
+            where
+                K: Borrow<Q>,
+                Q: Ord + ?Sized,
+            {
+                RangeIter {
+                    iter: self.$field.range(bounds),
+                }
+            }
+
+            fn len(&self) -> usize {
+                self.$field.len()
+            }
+        }
+    };
+}
+
+/// Module for [`View`] and it's related impls
+mod view {
+    use super::*;
+
+    /// Consistent view of the set at the certain version
+    pub struct View<'storage, K: Key> {
+        pub(crate) view: storage::View<'storage, K, ()>,
+    }
+
+    impl_set_read_only!(View<'_, K>, view);
+
+    impl<K: Key> View<'_, K> {
+        /// Iterate over keys present in `self` or `other` in order
+        pub fn union<'slf>(&'slf self, other: &'slf Self) -> Union<'slf, K> {
+            Union {
+                merge: Merge::new(self.iter(), other.iter()),
+            }
+        }
+
+        /// Iterate over keys present in both `self` and `other` in order
+        pub fn intersection<'slf>(&'slf self, other: &'slf Self) -> Intersection<'slf, K> {
+            Intersection {
+                merge: Merge::new(self.iter(), other.iter()),
+            }
+        }
+
+        /// Iterate over keys present in `self` but not in `other` in order
+        pub fn difference<'slf>(&'slf self, other: &'slf Self) -> Difference<'slf, K> {
+            Difference {
+                merge: Merge::new(self.iter(), other.iter()),
+            }
+        }
+    }
+}
+pub use view::View;
+
+/// Module for [`Block`] and it's related impls
+mod block {
+    use super::*;
+
+    /// Batched update to the set that can be reverted later
+    pub struct Block<'store, K: Key> {
+        pub(crate) block: storage::Block<'store, K, ()>,
+    }
+
+    impl<'store, K: Key> Block<'store, K> {
+        /// Create transaction for the block
+        pub fn transaction<'block>(&'block mut self) -> Transaction<'block, 'store, K>
+        where
+            'store: 'block,
+        {
+            Transaction {
+                transaction: self.block.transaction(),
+            }
+        }
+
+        /// Apply aggregated changes to the set
+        pub fn commit(self) {
+            self.block.commit();
+        }
+
+        /// Insert key into the set, return `true` if key wasn't present
+        pub fn insert(&mut self, key: K) -> bool {
+            self.block.insert(key, ()).is_none()
+        }
+
+        /// Remove key from the set, return `true` if key was present
+        pub fn remove(&mut self, key: K) -> bool {
+            self.block.remove(key).is_some()
+        }
+    }
+
+    impl_set_read_only!(Block<'_, K>, block);
+
+    /// Part of block's aggregated changes which applied or aborted at the same time
+    pub struct Transaction<'block, 'store, K: Key> {
+        pub(crate) transaction: storage::Transaction<'block, 'store, K, ()>,
+    }
+
+    impl<'block, 'store: 'block, K: Key> Transaction<'block, 'store, K> {
+        /// Apply aggregated changes of [`Transaction`] to the [`Block`]
+        pub fn apply(self) {
+            self.transaction.apply();
+        }
+
+        /// Insert key into the set, return `true` if key wasn't present
+        pub fn insert(&mut self, key: K) -> bool {
+            self.transaction.insert(key, ()).is_none()
+        }
+
+        /// Remove key from the set, return `true` if key was present
+        pub fn remove(&mut self, key: K) -> bool {
+            self.transaction.remove(key).is_some()
+        }
+    }
+
+    impl_set_read_only!(Transaction<'_, '_, K>, transaction);
+}
+pub use block::{Block, Transaction};
+
+mod iter {
+    use super::*;
+
+    /// Iterate over keys in block, view or transaction
+    pub struct Iter<'slf, K: Key> {
+        pub(crate) iter: storage::Iter<'slf, K, ()>,
+    }
+
+    /// Iterate over range of keys in block, view or transaction
+    pub struct RangeIter<'slf, K: Key> {
+        pub(crate) iter: storage::RangeIter<'slf, K, ()>,
+    }
+
+    impl<'slf, K: Key> Iterator for Iter<'slf, K> {
+        type Item = &'slf K;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            self.iter.next().map(|(key, _)| key)
+        }
+    }
+
+    impl<'slf, K: Key> Iterator for RangeIter<'slf, K> {
+        type Item = &'slf K;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            self.iter.next().map(|(key, _)| key)
+        }
+    }
+
+    /// Walk two ordered iterators simultaneously
+    pub(crate) struct Merge<'slf, K: Key> {
+        a: Peekable<Iter<'slf, K>>,
+        b: Peekable<Iter<'slf, K>>,
+    }
+
+    impl<'slf, K: Key> Merge<'slf, K> {
+        pub(crate) fn new(a: Iter<'slf, K>, b: Iter<'slf, K>) -> Self {
+            Self {
+                a: a.peekable(),
+                b: b.peekable(),
+            }
+        }
+
+        /// Get next key and whether it's present in `a` and `b`
+        fn next(&mut self) -> Option<(&'slf K, bool, bool)> {
+            let ordering = match (self.a.peek(), self.b.peek()) {
+                (None, None) => return None,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(a), Some(b)) => a.cmp(b),
+            };
+            match ordering {
+                Ordering::Less => self.a.next().map(|key| (key, true, false)),
+                Ordering::Greater => self.b.next().map(|key| (key, false, true)),
+                Ordering::Equal => {
+                    self.b.next();
+                    self.a.next().map(|key| (key, true, true))
+                }
+            }
+        }
+    }
+
+    /// Iterate over keys present in any of two views
+    pub struct Union<'slf, K: Key> {
+        pub(crate) merge: Merge<'slf, K>,
+    }
+
+    /// Iterate over keys present in both views
+    pub struct Intersection<'slf, K: Key> {
+        pub(crate) merge: Merge<'slf, K>,
+    }
+
+    /// Iterate over keys present in the first view but not in the second
+    pub struct Difference<'slf, K: Key> {
+        pub(crate) merge: Merge<'slf, K>,
+    }
+
+    impl<'slf, K: Key> Iterator for Union<'slf, K> {
+        type Item = &'slf K;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            self.merge.next().map(|(key, _, _)| key)
+        }
+    }
+
+    impl<'slf, K: Key> Iterator for Intersection<'slf, K> {
+        type Item = &'slf K;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            loop {
+                match self.merge.next()? {
+                    (key, true, true) => return Some(key),
+                    _ => continue,
+                }
+            }
+        }
+    }
+
+    impl<'slf, K: Key> Iterator for Difference<'slf, K> {
+        type Item = &'slf K;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            loop {
+                match self.merge.next()? {
+                    (key, true, false) => return Some(key),
+                    _ => continue,
+                }
+            }
+        }
+    }
+}
+use iter::Merge;
+pub use iter::{Difference, Intersection, Iter, RangeIter, Union};
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    use proptest::proptest;
+
+    #[test]
+    fn insert_remove() {
+        let set = StorageSet::<u64>::new();
+
+        {
+            let mut block = set.block();
+            assert!(block.insert(0));
+            assert!(block.insert(1));
+            assert!(!block.insert(1));
+            block.commit();
+        }
+
+        let view1 = set.view();
+
+        {
+            let mut block = set.block();
+            assert!(block.remove(0));
+            assert!(!block.remove(2));
+            block.insert(3);
+            block.commit();
+        }
+
+        let view2 = set.view();
+
+        assert!(view1.iter().copied().eq([0, 1]));
+        assert!(view2.iter().copied().eq([1, 3]));
+        assert!(view2.contains(&3));
+        assert!(!view2.contains(&0));
+        assert!(view2.range(2..).copied().eq([3]));
+
+        set.block_and_revert().commit();
+
+        let view3 = set.view();
+        assert!(view3.iter().copied().eq([0, 1]));
+    }
+
+    #[test]
+    fn transaction_step() {
+        let set = StorageSet::<u64>::new();
+
+        let mut block = set.block();
+
+        {
+            let mut transaction = block.transaction();
+            transaction.insert(0);
+            transaction.apply();
+        }
+
+        {
+            let mut transaction = block.transaction();
+            transaction.remove(0);
+            transaction.insert(1);
+        }
+
+        block.commit();
+
+        let view = set.view();
+        assert!(view.iter().copied().eq([0]));
+    }
+
+    proptest! {
+        #[test]
+        fn set_operations_consistent_with_btreeset(a: BTreeSet<u8>, b: BTreeSet<u8>) {
+            let set_a = a.iter().copied().collect::<StorageSet<u8>>();
+            let set_b = b.iter().copied().collect::<StorageSet<u8>>();
+            let (view_a, view_b) = (set_a.view(), set_b.view());
+
+            assert!(view_a.union(&view_b).eq(a.union(&b)));
+            assert!(view_a.intersection(&view_b).eq(a.intersection(&b)));
+            assert!(view_a.difference(&view_b).eq(a.difference(&b)));
+        }
+    }
+}
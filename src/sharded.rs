@@ -0,0 +1,452 @@
+//! Multi-version key value storage partitioned into independent shards
+
+use std::{
+    borrow::Borrow,
+    sync::{Mutex, PoisonError},
+};
+
+use crate::{
+    storage::{self, Storage, StorageReadOnly},
+    version::Version,
+    Key, Value,
+};
+
+/// Function which assigns key to the shard
+pub type ShardFn<K> = dyn Fn(&K) -> usize + Send + Sync;
+
+/// Multi-version key value storage partitioned into several [`Storage`]s by the user supplied shard function
+///
+/// Every shard has it's own writer so blocks for different shards can be executed concurrently.
+pub struct ShardedStorage<K: Key, V: Value> {
+    /// Independent storages for every shard
+    pub(crate) shards: Vec<Storage<K, V>>,
+    /// Function to assign key to the shard
+    pub(crate) shard: Box<ShardFn<K>>,
+    /// Global version incremented by commit of any shard, views retry while commit is published
+    pub(crate) version: Version,
+    /// Lock which serializes commits of different shards, views don't take it
+    pub(crate) commit: Mutex<()>,
+}
+
+impl<K: Key, V: Value> ShardedStorage<K, V> {
+    /// Construct new [`Self`] with `shards` amount of shards
+    ///
+    /// Result of `shard` function is taken modulo `shards`.
+    ///
+    /// # Panics
+    ///
+    /// If `shards` is zero.
+    pub fn new(shards: usize, shard: impl Fn(&K) -> usize + Send + Sync + 'static) -> Self {
+        assert!(shards > 0, "sharded storage requires at least one shard");
+        Self {
+            shards: (0..shards).map(|_| Storage::new()).collect(),
+            shard: Box::new(shard),
+            version: Version::new(0),
+            commit: Mutex::new(()),
+        }
+    }
+
+    /// Get amount of shards
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Get shard to which key belongs
+    pub fn shard_of(&self, key: &K) -> usize {
+        (self.shard)(key) % self.shards.len()
+    }
+
+    /// Create persistent view of all shards at the same global version
+    pub fn view(&self) -> View<'_, K, V> {
+        self.version.read(|version| View {
+            shards: self.shards.iter().map(Storage::view).collect(),
+            version,
+            storage: self,
+        })
+    }
+
+    /// Create block to aggregate updates of the shard
+    ///
+    /// # Panics
+    ///
+    /// If `shard` is out of bounds.
+    pub fn block(&self, shard: usize) -> Block<'_, K, V> {
+        Block {
+            block: self.shards[shard].block(),
+            shard,
+            storage: self,
+        }
+    }
+
+    /// Create block to aggregate updates of the shard and revert changes created in the latest block of this shard
+    ///
+    /// # Panics
+    ///
+    /// If `shard` is out of bounds.
+    pub fn block_and_revert(&self, shard: usize) -> Block<'_, K, V> {
+        Block {
+            block: self.shards[shard].block_and_revert(),
+            shard,
+            storage: self,
+        }
+    }
+}
+
+/// Module for [`View`] and it's related impls
+mod view {
+    use core::iter::Peekable;
+
+    use super::*;
+
+    /// Consistent view of all shards at the certain global version
+    pub struct View<'storage, K: Key, V: Value> {
+        pub(crate) shards: Vec<storage::View<'storage, K, V>>,
+        pub(crate) version: u64,
+        pub(crate) storage: &'storage ShardedStorage<K, V>,
+    }
+
+    impl<'storage, K: Key, V: Value> View<'storage, K, V> {
+        /// Global version at which view was created
+        pub fn version(&self) -> u64 {
+            self.version
+        }
+
+        /// Get view of the single shard
+        ///
+        /// # Panics
+        ///
+        /// If `shard` is out of bounds.
+        pub fn shard(&self, shard: usize) -> &storage::View<'storage, K, V> {
+            &self.shards[shard]
+        }
+
+        /// Read entry from the shard key belongs to
+        pub fn get(&self, key: &K) -> Option<&V> {
+            self.shards[self.storage.shard_of(key)].get(key)
+        }
+
+        /// Iterate over entries of all shards ordered by key
+        pub fn iter(&self) -> Iter<'_, K, V> {
+            Iter {
+                iters: self
+                    .shards
+                    .iter()
+                    .map(|shard| shard.iter().peekable())
+                    .collect(),
+            }
+        }
+
+        /// Get amount of entries in all shards
+        pub fn len(&self) -> usize {
+            self.shards.iter().map(StorageReadOnly::len).sum()
+        }
+
+        /// Check if all shards have no entries
+        pub fn is_empty(&self) -> bool {
+            self.shards.iter().all(StorageReadOnly::is_empty)
+        }
+    }
+
+    /// Iterate over entries of all shards ordered by key
+    pub struct Iter<'slf, K: Key, V: Value> {
+        pub(crate) iters: Vec<Peekable<storage::Iter<'slf, K, V>>>,
+    }
+
+    impl<'slf, K: Key, V: Value> Iterator for Iter<'slf, K, V> {
+        type Item = (&'slf K, &'slf V);
+
+        fn next(&mut self) -> Option<Self::Item> {
+            // Shards have disjoint keys so it's enough to pick the smallest head
+            let (_, iter) = self
+                .iters
+                .iter_mut()
+                .filter_map(|iter| iter.peek().map(|(key, _)| *key).zip(Some(iter)))
+                .min_by(|(a, _), (b, _)| a.cmp(b))?;
+            iter.next()
+        }
+    }
+}
+pub use view::{Iter, View};
+
+/// Module for [`Block`] and it's related impls
+mod block {
+    use super::*;
+
+    /// Batched update to the single shard that can be reverted later
+    pub struct Block<'store, K: Key, V: Value> {
+        pub(crate) block: storage::Block<'store, K, V>,
+        pub(crate) shard: usize,
+        pub(crate) storage: &'store ShardedStorage<K, V>,
+    }
+
+    impl<'store, K: Key, V: Value> Block<'store, K, V> {
+        /// Shard which is updated by the block
+        pub fn shard(&self) -> usize {
+            self.shard
+        }
+
+        /// Create transaction for the block
+        pub fn transaction<'block>(&'block mut self) -> Transaction<'block, 'store, K, V>
+        where
+            'store: 'block,
+        {
+            Transaction {
+                transaction: self.block.transaction(),
+                shard: self.shard,
+                storage: self.storage,
+            }
+        }
+
+        /// Apply aggregated changes to the shard and advance global version
+        pub fn commit(self) {
+            // Poisoning is ignored: publication of the version ends even if commit panics
+            let _commit = self
+                .storage
+                .commit
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let mut publish = self.storage.version.publish();
+            self.block.commit();
+            *publish.version() += 1;
+        }
+
+        /// Get mutable access to the value stored in
+        ///
+        /// # Panics
+        ///
+        /// If key doesn't belong to the block's shard.
+        pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+            assert_shard(self.storage, self.shard, key);
+            self.block.get_mut(key)
+        }
+
+        /// Insert key value into the shard
+        ///
+        /// # Panics
+        ///
+        /// If key doesn't belong to the block's shard.
+        pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+            assert_shard(self.storage, self.shard, &key);
+            self.block.insert(key, value)
+        }
+
+        /// Remove key value from the shard
+        ///
+        /// # Panics
+        ///
+        /// If key doesn't belong to the block's shard.
+        pub fn remove(&mut self, key: K) -> Option<V> {
+            assert_shard(self.storage, self.shard, &key);
+            self.block.remove(key)
+        }
+    }
+
+    impl<K: Key, V: Value> StorageReadOnly<K, V> for Block<'_, K, V> {
+        fn get<Q>(&self, key: &Q) -> Option<&V>
+        where
+            K: Borrow<Q>,
+            Q: Ord + ?Sized,
+        {
+            self.block.get(key)
+        }
+
+        fn iter(&self) -> storage::Iter<'_, K, V> {
+            self.block.iter()
+        }
+
+        fn range<Q>(&self, bounds: impl core::ops::RangeBounds<Q>) -> storage::RangeIter<'_, K, V>
+        where
+            K: Borrow<Q>,
+            Q: Ord + ?Sized,
+        {
+            self.block.range(bounds)
+        }
+
+        fn len(&self) -> usize {
+            self.block.len()
+        }
+    }
+
+    /// Part of block's aggregated changes which applied or aborted at the same time
+    pub struct Transaction<'block, 'store, K: Key, V: Value> {
+        pub(crate) transaction: storage::Transaction<'block, 'store, K, V>,
+        pub(crate) shard: usize,
+        pub(crate) storage: &'store ShardedStorage<K, V>,
+    }
+
+    impl<'block, 'store: 'block, K: Key, V: Value> Transaction<'block, 'store, K, V> {
+        /// Apply aggregated changes of [`Transaction`] to the [`Block`]
+        pub fn apply(self) {
+            self.transaction.apply();
+        }
+
+        /// Get mutable access to the value stored in
+        ///
+        /// # Panics
+        ///
+        /// If key doesn't belong to the block's shard.
+        pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+            assert_shard(self.storage, self.shard, key);
+            self.transaction.get_mut(key)
+        }
+
+        /// Insert key value into the shard
+        ///
+        /// # Panics
+        ///
+        /// If key doesn't belong to the block's shard.
+        pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+            assert_shard(self.storage, self.shard, &key);
+            self.transaction.insert(key, value)
+        }
+
+        /// Remove key value from the shard
+        ///
+        /// # Panics
+        ///
+        /// If key doesn't belong to the block's shard.
+        pub fn remove(&mut self, key: K) -> Option<V> {
+            assert_shard(self.storage, self.shard, &key);
+            self.transaction.remove(key)
+        }
+    }
+
+    impl<K: Key, V: Value> StorageReadOnly<K, V> for Transaction<'_, '_, K, V> {
+        fn get<Q>(&self, key: &Q) -> Option<&V>
+        where
+            K: Borrow<Q>,
+            Q: Ord + ?Sized,
+        {
+            self.transaction.get(key)
+        }
+
+        fn iter(&self) -> storage::Iter<'_, K, V> {
+            self.transaction.iter()
+        }
+
+        fn range<Q>(&self, bounds: impl core::ops::RangeBounds<Q>) -> storage::RangeIter<'_, K, V>
+        where
+            K: Borrow<Q>,
+            Q: Ord + ?Sized,
+        {
+            self.transaction.range(bounds)
+        }
+
+        fn len(&self) -> usize {
+            self.transaction.len()
+        }
+    }
+
+    fn assert_shard<K: Key, V: Value>(storage: &ShardedStorage<K, V>, shard: usize, key: &K) {
+        let key_shard = storage.shard_of(key);
+        assert_eq!(
+            key_shard, shard,
+            "key {key:?} belongs to shard {key_shard} but block is for shard {shard}"
+        );
+    }
+}
+pub use block::{Block, Transaction};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sharded() -> ShardedStorage<u64, u64> {
+        ShardedStorage::new(4, |key: &u64| *key as usize)
+    }
+
+    #[test]
+    fn get() {
+        let storage = sharded();
+
+        for shard in 0..storage.shards() {
+            let mut block = storage.block(shard);
+            for key in (0..16).filter(|key| storage.shard_of(key) == shard) {
+                block.insert(key, key * 10);
+            }
+            block.commit();
+        }
+
+        let view = storage.view();
+        assert_eq!(view.version(), 4);
+        assert_eq!(view.len(), 16);
+        for key in 0..16 {
+            assert_eq!(view.get(&key), Some(&(key * 10)));
+        }
+        assert_eq!(view.get(&16), None);
+        assert_eq!(view.shard(1).len(), 4);
+    }
+
+    #[test]
+    fn iter_is_ordered() {
+        let storage = sharded();
+
+        for shard in 0..storage.shards() {
+            let mut block = storage.block(shard);
+            for key in (0..32).rev().filter(|key| storage.shard_of(key) == shard) {
+                block.insert(key, key);
+            }
+            block.commit();
+        }
+
+        let view = storage.view();
+        assert!(view.iter().map(|(key, _)| *key).eq(0..32));
+    }
+
+    #[test]
+    fn revert_shard() {
+        let storage = sharded();
+
+        for value in 0..2 {
+            for shard in 0..storage.shards() {
+                let mut block = storage.block(shard);
+                block.insert(shard as u64, value);
+                block.commit();
+            }
+        }
+
+        storage.block_and_revert(1).commit();
+
+        let view = storage.view();
+        assert_eq!(view.get(&0), Some(&1));
+        assert_eq!(view.get(&1), Some(&0));
+        assert_eq!(view.get(&2), Some(&1));
+    }
+
+    #[test]
+    #[should_panic(expected = "belongs to shard")]
+    fn insert_into_foreign_shard() {
+        let storage = sharded();
+        storage.block(0).insert(1, 1);
+    }
+
+    #[test]
+    fn concurrent_writers() {
+        const BLOCKS: u64 = 100;
+
+        let storage = sharded();
+
+        std::thread::scope(|s| {
+            for shard in 0..storage.shards() {
+                let storage = &storage;
+                s.spawn(move || {
+                    for i in 0..BLOCKS {
+                        let mut block = storage.block(shard);
+                        block.insert(shard as u64 + i * 4, i);
+                        block.commit();
+                    }
+                });
+            }
+
+            s.spawn(|| {
+                for _ in 0..BLOCKS {
+                    // Every commit adds exactly one key so amount of keys must match global version
+                    let view = storage.view();
+                    assert_eq!(view.len() as u64, view.version());
+                }
+            });
+        });
+
+        assert_eq!(storage.view().len() as u64, BLOCKS * 4);
+    }
+}
@@ -0,0 +1,1066 @@
+//! Chunked binary snapshots of [`Storage`] and [`Cell`] protected by checksums
+//!
+//! Snapshot layout:
+//! - header: [`MAGIC`], format version (`u32`) and kind of snapshot (`u8`)
+//! - storage version (`u64`) for storage snapshots, followed by base version (`u64`) for incremental ones,
+//!   or base version (`u64`), kind of the block (`u8`) and version of the redo (`u64`, zero if there is nothing to redo)
+//!   for pending blocks
+//! - metadata of the version for storage and incremental snapshots since format `2`:
+//!   `0_u8` if there is none, or `1_u8`, length (`u32`) and encoded metadata
+//! - chunks: payload length (`u32`, non-zero), payload of encoded records, CRC32 of payload (`u32`)
+//! - end: zero length (`u32`) and total amount of records (`u64`)
+//! - digest: CRC32 of all preceding bytes (`u32`)
+//!
+//! Records never span chunks. All integers are little endian.
+
+use core::fmt;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, Read, Write},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use concread::{bptree::BptreeMap, EbrCell};
+use crc32fast::Hasher;
+
+use crate::{
+    cell::{self, Cell},
+    codec::{Decode, Encode},
+    storage::{
+        self, BlockMeta, Meta, Prev, Quota, QuotaExceeded, Redo, Reverted, Storage, StorageReadOnly,
+    },
+    version::Version,
+    Error, Key, Value,
+};
+
+/// Magic bytes which start every snapshot
+pub const MAGIC: [u8; 8] = *b"MVSNAPSH";
+/// Version of the snapshot layout written by this crate
+///
+/// Format `1` has no metadata, such snapshots are still read.
+pub const FORMAT_VERSION: u32 = 2;
+/// Payload size after which chunk is flushed
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Kind of the snapshot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum Kind {
+    Storage = 0,
+    Cell = 1,
+    Incremental = 2,
+    PendingBlock = 3,
+}
+
+/// Kind of the pending block, decides what happens to revert and redo on commit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum PendingKind {
+    /// Block which can be reverted after commit
+    Block = 0,
+    /// Block which reverted the latest committed block
+    Revert = 1,
+    /// Block which had nothing to revert and keeps redo of the previous revert
+    RevertNothing = 2,
+}
+
+impl PendingKind {
+    fn of<K: Key, V: Value>(block: &storage::Block<'_, K, V>) -> Self {
+        match block.reverted {
+            _ if block.keep_revert => Self::Block,
+            Reverted::Nothing => Self::RevertNothing,
+            Reverted::Block { .. } => Self::Revert,
+        }
+    }
+}
+
+/// Record of the pending block: key, it's value, value before the block and value to redo
+type PendingRecord<K, V> = (K, Option<V>, Option<Option<V>>, Option<Option<V>>);
+
+/// Error which might occur during reading of the snapshot
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// Failed to read from the underlying reader
+    Io {
+        /// Offset in bytes from the start of the snapshot
+        offset: u64,
+        error: io::Error,
+    },
+    /// Checksum of the chunk doesn't match it's payload
+    Checksum {
+        /// Offset in bytes of the chunk
+        offset: u64,
+    },
+    /// Digest of the snapshot doesn't match it's content
+    Digest {
+        /// Offset in bytes of the digest
+        offset: u64,
+    },
+    /// Incremental snapshot is based on the version other than version of the storage
+    Base {
+        /// Offset in bytes of the base version
+        offset: u64,
+        /// Base version of the incremental snapshot
+        base: u64,
+        /// Version of the storage snapshot is applied to
+        version: u64,
+    },
+    /// Snapshot content is not valid
+    Malformed {
+        /// Offset in bytes of the invalid content
+        offset: u64,
+        reason: String,
+    },
+    /// Record doesn't fit into quota of the storage
+    Quota {
+        /// Offset in bytes of the record
+        offset: u64,
+        error: QuotaExceeded,
+    },
+}
+
+/// Reason why record of the snapshot is rejected
+enum RecordError {
+    Malformed(String),
+    Quota(QuotaExceeded),
+}
+
+impl From<String> for RecordError {
+    fn from(reason: String) -> Self {
+        Self::Malformed(reason)
+    }
+}
+
+impl From<Error> for RecordError {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::Quota(error) => Self::Quota(error),
+            error => Self::Malformed(error.to_string()),
+        }
+    }
+}
+
+impl SnapshotError {
+    /// Offset in bytes from the start of the snapshot at which error occurred
+    pub fn offset(&self) -> u64 {
+        match self {
+            Self::Io { offset, .. }
+            | Self::Checksum { offset }
+            | Self::Digest { offset }
+            | Self::Base { offset, .. }
+            | Self::Malformed { offset, .. }
+            | Self::Quota { offset, .. } => *offset,
+        }
+    }
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { offset, error } => {
+                write!(f, "failed to read snapshot at byte {offset}: {error}")
+            }
+            Self::Checksum { offset } => write!(f, "checksum mismatch of chunk at byte {offset}"),
+            Self::Digest { offset } => write!(f, "snapshot digest mismatch at byte {offset}"),
+            Self::Base {
+                offset,
+                base,
+                version,
+            } => write!(
+                f,
+                "incremental snapshot at byte {offset} is based on version {base}, but storage has version {version}"
+            ),
+            Self::Malformed { offset, reason } => {
+                write!(f, "malformed snapshot at byte {offset}: {reason}")
+            }
+            Self::Quota { offset, error } => {
+                write!(f, "record at byte {offset} exceeds quota: {error}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { error, .. } => Some(error),
+            Self::Quota { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Writer which splits records into chunks and computes digest of written bytes
+struct SnapshotWriter<W> {
+    writer: W,
+    digest: Hasher,
+    chunk: Vec<u8>,
+    records: u64,
+}
+
+impl<W: Write> SnapshotWriter<W> {
+    fn new(mut writer: W, kind: Kind) -> io::Result<Self> {
+        let mut header = MAGIC.to_vec();
+        FORMAT_VERSION.encode(&mut header)?;
+        (kind as u8).encode(&mut header)?;
+        writer.write_all(&header)?;
+        let mut digest = Hasher::new();
+        digest.update(&header);
+        Ok(Self {
+            writer,
+            digest,
+            chunk: Vec::new(),
+            records: 0,
+        })
+    }
+
+    /// Write version into the header
+    fn version(&mut self, version: u64) -> io::Result<()> {
+        self.write(&version.to_le_bytes())
+    }
+
+    /// Write metadata of the version into the header
+    fn meta(&mut self, meta: &Meta) -> io::Result<()> {
+        let Some(meta) = meta else {
+            return self.write(&[0]);
+        };
+        let len = u32::try_from(meta.bytes.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "metadata is too large"))?;
+        self.write(&[1])?;
+        self.write(&len.to_le_bytes())?;
+        self.write(&meta.bytes)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.writer.write_all(bytes)?;
+        self.digest.update(bytes);
+        Ok(())
+    }
+
+    fn record(&mut self, record: &impl Encode) -> io::Result<()> {
+        record.encode(&mut self.chunk)?;
+        self.records += 1;
+        if self.chunk.len() >= CHUNK_SIZE {
+            self.flush_chunk()?;
+        }
+        Ok(())
+    }
+
+    fn flush_chunk(&mut self) -> io::Result<()> {
+        if self.chunk.is_empty() {
+            return Ok(());
+        }
+        let len = u32::try_from(self.chunk.len()).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "record doesn't fit into chunk")
+        })?;
+        let chunk = core::mem::take(&mut self.chunk);
+        self.write(&len.to_le_bytes())?;
+        self.write(&chunk)?;
+        self.write(&crc32fast::hash(&chunk).to_le_bytes())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.flush_chunk()?;
+        self.write(&0_u32.to_le_bytes())?;
+        self.write(&self.records.to_le_bytes())?;
+        let digest = self.digest.clone().finalize();
+        self.writer.write_all(&digest.to_le_bytes())?;
+        self.writer.flush()
+    }
+}
+
+/// Reader which tracks offset and computes digest of read bytes
+struct SnapshotReader<R> {
+    reader: R,
+    digest: Hasher,
+    offset: u64,
+    /// Format version of the snapshot
+    format: u32,
+}
+
+impl<R: Read> SnapshotReader<R> {
+    fn new(reader: R, kind: Kind) -> Result<Self, SnapshotError> {
+        let mut slf = Self {
+            reader,
+            digest: Hasher::new(),
+            offset: 0,
+            format: FORMAT_VERSION,
+        };
+        let magic = slf.read_bytes(MAGIC.len())?;
+        if magic != MAGIC {
+            return Err(SnapshotError::Malformed {
+                offset: 0,
+                reason: "invalid magic".to_owned(),
+            });
+        }
+        let offset = slf.offset;
+        slf.format = u32::from_le_bytes(slf.read_array()?);
+        if !(1..=FORMAT_VERSION).contains(&slf.format) {
+            return Err(SnapshotError::Malformed {
+                offset,
+                reason: format!("unsupported format {}", slf.format),
+            });
+        }
+        let offset = slf.offset;
+        let [actual] = slf.read_array()?;
+        if actual != kind as u8 {
+            return Err(SnapshotError::Malformed {
+                offset,
+                reason: format!("expected snapshot kind {}, got {actual}", kind as u8),
+            });
+        }
+        Ok(slf)
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>, SnapshotError> {
+        let mut bytes = Vec::new();
+        // Length might come from corrupted input so grow buffer while reading instead of preallocating
+        let read = (&mut self.reader)
+            .take(len as u64)
+            .read_to_end(&mut bytes)
+            .map_err(|error| SnapshotError::Io {
+                offset: self.offset + bytes.len() as u64,
+                error,
+            })?;
+        if read < len {
+            return Err(SnapshotError::Io {
+                offset: self.offset + read as u64,
+                error: io::ErrorKind::UnexpectedEof.into(),
+            });
+        }
+        self.digest.update(&bytes);
+        self.offset += len as u64;
+        Ok(bytes)
+    }
+
+    /// Read version from the header
+    fn version(&mut self) -> Result<u64, SnapshotError> {
+        self.read_array().map(u64::from_le_bytes)
+    }
+
+    /// Read metadata of the version from the header, snapshots of format `1` have none
+    fn meta(&mut self) -> Result<Meta, SnapshotError> {
+        if self.format < 2 {
+            return Ok(None);
+        }
+        let offset = self.offset;
+        match self.read_array()? {
+            [0] => Ok(None),
+            [1] => {
+                let len = u32::from_le_bytes(self.read_array()?);
+                let bytes = self.read_bytes(len as usize)?;
+                Ok(Some(Arc::new(BlockMeta::encoded(bytes))))
+            }
+            [flag] => Err(SnapshotError::Malformed {
+                offset,
+                reason: format!("invalid metadata flag {flag}"),
+            }),
+        }
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], SnapshotError> {
+        let bytes = self.read_bytes(N)?;
+        Ok(bytes.try_into().expect("exactly N bytes are read"))
+    }
+
+    /// Read records of the snapshot chunk by chunk and check trailing digest
+    fn records<T: Decode>(
+        mut self,
+        mut record: impl FnMut(u64, T) -> Result<(), RecordError>,
+    ) -> Result<(), SnapshotError> {
+        let mut records = 0_u64;
+        loop {
+            let offset = self.offset;
+            let len = u32::from_le_bytes(self.read_array()?);
+            if len == 0 {
+                break;
+            }
+            let payload_offset = self.offset;
+            let payload = self.read_bytes(len as usize)?;
+            let crc = u32::from_le_bytes(self.read_array()?);
+            if crc != crc32fast::hash(&payload) {
+                return Err(SnapshotError::Checksum { offset });
+            }
+
+            let mut cursor = payload.as_slice();
+            while !cursor.is_empty() {
+                let offset = payload_offset + (payload.len() - cursor.len()) as u64;
+                T::decode(&mut cursor)
+                    .map_err(|error| RecordError::Malformed(error.to_string()))
+                    .and_then(|value| record(records, value))
+                    .map_err(|error| match error {
+                        RecordError::Malformed(reason) => {
+                            SnapshotError::Malformed { offset, reason }
+                        }
+                        RecordError::Quota(error) => SnapshotError::Quota { offset, error },
+                    })?;
+                records += 1;
+            }
+        }
+
+        let offset = self.offset;
+        let expected = u64::from_le_bytes(self.read_array()?);
+        if expected != records {
+            return Err(SnapshotError::Malformed {
+                offset,
+                reason: format!("expected {expected} records, got {records}"),
+            });
+        }
+
+        let offset = self.offset;
+        let digest = self.digest.clone().finalize();
+        if u32::from_le_bytes(self.read_array()?) != digest {
+            return Err(SnapshotError::Digest { offset });
+        }
+        Ok(())
+    }
+}
+
+impl<K: Key + Encode + Decode, V: Value + Encode + Decode> Storage<K, V> {
+    /// Write entries of the view as binary snapshot
+    pub fn write_snapshot<W: Write>(
+        view: &storage::View<'_, K, V>,
+        writer: W,
+    ) -> Result<(), Error> {
+        let mut writer = SnapshotWriter::new(writer, Kind::Storage)?;
+        writer.version(view.version)?;
+        writer.meta(&view.meta)?;
+        for (key, value) in view.iter() {
+            writer.record(&(key, value))?;
+        }
+        Ok(writer.finish()?)
+    }
+
+    /// Build storage from the binary snapshot written by [`Storage::write_snapshot`]
+    ///
+    /// Snapshot holds the view only, so loaded storage has neither revert nor redo:
+    /// the first [`Storage::block_and_revert`] reverts nothing and [`Storage::block_and_redo`] fails.
+    /// Use `serde` snapshots to keep them.
+    ///
+    /// Records are inserted one at a time within a single write transaction,
+    /// tree can't be built from sorted records in bulk as `concread` keeps its nodes private.
+    pub fn read_snapshot<R: Read>(reader: R) -> Result<Self, Error> {
+        let blocks = BptreeMap::new();
+        let mut write = blocks.write();
+        let mut prev: Option<K> = None;
+        let mut reader = SnapshotReader::new(reader, Kind::Storage)?;
+        let version = reader.version()?;
+        let meta = reader.meta()?;
+        reader.records(|record, (key, value): (K, V)| {
+            if prev.as_ref().is_some_and(|prev| prev >= &key) {
+                return Err(format!("record {record} is out of order or duplicate").into());
+            }
+            prev = Some(key.clone());
+            write.insert(key, value);
+            Ok(())
+        })?;
+        write.commit();
+        Ok(Storage {
+            revert: EbrCell::new(BTreeMap::new()),
+            blocks,
+            indexes: Vec::new(),
+            version: Version::new(version),
+            modified: None,
+            tracked_since: AtomicU64::new(version),
+            // Snapshot doesn't keep revert nor redo
+            redo: EbrCell::new(Redo::default()),
+            revertible: AtomicBool::new(false),
+            receipts: EbrCell::new(Vec::new()),
+            meta: EbrCell::new(meta),
+            quota: Quota::new(),
+            bytes: AtomicUsize::new(0),
+        })
+    }
+
+    /// Write entries changed after `base_version` as incremental snapshot, removed entries are written as tombstones
+    ///
+    /// Fails if changes since `base_version` are not tracked by this storage,
+    /// e.g. tracking wasn't enabled with [`Storage::track_changes`] before `base_version`
+    /// or they were forgotten by [`Storage::prune_changes`].
+    pub fn export_incremental<W: Write>(&self, base_version: u64, writer: W) -> Result<(), Error> {
+        let Some(modified) = &self.modified else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "storage doesn't track changes",
+            )
+            .into());
+        };
+        let (version, blocks, modified, meta) = self.version.read(|version| {
+            (
+                version,
+                self.blocks.read(),
+                modified.read(),
+                self.meta.read(),
+            )
+        });
+        // Loaded after the read of `modified`, so it covers every pruned change
+        let tracked_since = self.tracked_since.load(Ordering::Relaxed);
+        if base_version < tracked_since || base_version > version {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "changes since version {base_version} are unknown, storage tracks changes from version {tracked_since} to {version}",
+                ),
+            ).into());
+        }
+
+        let mut writer = SnapshotWriter::new(writer, Kind::Incremental)?;
+        writer.version(version)?;
+        writer.version(base_version)?;
+        writer.meta(&meta)?;
+        for (key, _) in modified
+            .iter()
+            .filter(|(_, changed)| **changed > base_version)
+        {
+            writer.record(&(key, blocks.get(key)))?;
+        }
+        Ok(writer.finish()?)
+    }
+
+    /// Apply incremental snapshot written by [`Storage::export_incremental`] on top of the storage at it's base version
+    ///
+    /// Changes are applied as a single block which can be reverted, metadata of the exported version is attached to it.
+    /// Fails without changing the storage if changes exceed quota of the storage.
+    /// Snapshot exported at it's base version holds no changes, so it's checked but no block is committed.
+    pub fn import_incremental<R: Read>(&self, reader: R) -> Result<(), Error> {
+        let mut reader = SnapshotReader::new(reader, Kind::Incremental)?;
+        let version = reader.version()?;
+        let offset = reader.offset;
+        let base = reader.version()?;
+        let check_base = |current| {
+            if base == current {
+                Ok(())
+            } else {
+                Err(SnapshotError::Base {
+                    offset,
+                    base,
+                    version: current,
+                })
+            }
+        };
+        if version < base {
+            return Err(SnapshotError::Malformed {
+                offset,
+                reason: format!("base version {base} is newer than version {version}"),
+            }
+            .into());
+        }
+        let meta = reader.meta()?;
+        if version == base {
+            check_base(self.version())?;
+            reader.records(|record, _: (K, Option<V>)| {
+                Err(format!("record {record} of snapshot without changes").into())
+            })?;
+            return Ok(());
+        }
+
+        let mut block = self.block();
+        check_base(block.modified.version - 1)?;
+        block.modified.version = version;
+        reader.records(|_, (key, value): (K, Option<V>)| {
+            match value {
+                Some(value) => block.try_insert(key, value)?,
+                None => block.try_remove(key)?,
+            };
+            Ok(())
+        })?;
+        block.commit_meta(meta);
+        Ok(())
+    }
+}
+
+impl<K: Key + Encode + Decode, V: Value + Encode + Decode> Storage<K, V> {
+    /// Rebuild pending block written by [`Block::write_pending`](storage::Block::write_pending)
+    ///
+    /// Writes are replayed with quota checks, revert and redo of the block are restored as they were.
+    /// Fails if storage is not at the version block was based on or writes exceed quota of the storage.
+    pub fn resume_block<R: Read>(&self, reader: R) -> Result<storage::Block<'_, K, V>, Error> {
+        let mut block = self.block();
+        let mut reader = SnapshotReader::new(reader, Kind::PendingBlock)?;
+        let offset = reader.offset;
+        let base = reader.version()?;
+        let current = block.modified.version - 1;
+        if base != current {
+            return Err(SnapshotError::Base {
+                offset,
+                base,
+                version: current,
+            }
+            .into());
+        }
+        let offset = reader.offset;
+        let kind = match reader.read_array()? {
+            [0] => PendingKind::Block,
+            [1] => PendingKind::Revert,
+            [2] => PendingKind::RevertNothing,
+            [kind] => {
+                return Err(SnapshotError::Malformed {
+                    offset,
+                    reason: format!("unknown kind of the block {kind}"),
+                }
+                .into())
+            }
+        };
+        let redo_version = reader.version()?;
+        let mut revert = BTreeMap::new();
+        let mut redo = BTreeMap::new();
+        reader.records(|_, (key, value, prev, next): PendingRecord<K, V>| {
+            // Keys of the redo are written only by the revert itself
+            let written = prev.is_some() || (kind == PendingKind::Revert && next.is_some());
+            if let Some(prev) = prev {
+                revert.insert(key.clone(), Prev::new(prev));
+            }
+            if let Some(next) = next {
+                redo.insert(key.clone(), next);
+            }
+            if written {
+                match value {
+                    Some(value) => block.try_insert(key, value)?,
+                    None => block.try_remove(key)?,
+                };
+            }
+            Ok(())
+        })?;
+        // Replayed writes recorded values of the storage, restore the ones recorded by the block
+        block.prev = revert;
+        block.reverted = match kind {
+            PendingKind::Revert => Reverted::Block {
+                version: base,
+                keys: redo.len(),
+            },
+            PendingKind::Block | PendingKind::RevertNothing => Reverted::Nothing,
+        };
+        block.keep_revert = kind == PendingKind::Block;
+        block.carry_redo = kind == PendingKind::RevertNothing;
+        *block.redo.get_mut() = Redo {
+            version: (redo_version != 0).then_some(redo_version),
+            changes: redo,
+        };
+        Ok(block)
+    }
+}
+
+impl<K: Key + Encode, V: Value + Encode> storage::Block<'_, K, V> {
+    /// Write changes made by the block so far, so that it can be resumed with [`Storage::resume_block`]
+    pub fn write_pending<W: Write>(&self, writer: W) -> Result<(), Error> {
+        let mut writer = SnapshotWriter::new(writer, Kind::PendingBlock)?;
+        writer.version(self.modified.version - 1)?;
+        writer.write(&[PendingKind::of(self) as u8])?;
+        writer.version(self.redo.version.unwrap_or(0))?;
+        let keys = self
+            .prev
+            .keys()
+            .chain(self.redo.changes.keys())
+            .collect::<BTreeSet<_>>();
+        for key in keys {
+            writer.record(&(
+                key,
+                self.blocks.get(key),
+                self.prev.get(key).map(|prev| &prev.value),
+                self.redo.changes.get(key),
+            ))?;
+        }
+        Ok(writer.finish()?)
+    }
+}
+
+impl<V: Value + Encode + Decode> Cell<V> {
+    /// Write value of the view as binary snapshot
+    pub fn write_snapshot<W: Write>(view: &cell::View<'_, V>, writer: W) -> Result<(), Error> {
+        let mut writer = SnapshotWriter::new(writer, Kind::Cell)?;
+        writer.record(view.get())?;
+        Ok(writer.finish()?)
+    }
+
+    /// Build cell from the binary snapshot written by [`Cell::write_snapshot`]
+    ///
+    /// Snapshot holds the value only, so loaded cell has neither revert nor redo.
+    pub fn read_snapshot<R: Read>(reader: R) -> Result<Self, Error> {
+        let mut value = None;
+        SnapshotReader::new(reader, Kind::Cell)?.records(|record, v: V| {
+            if record > 0 {
+                return Err("cell snapshot has more than one value".to_owned().into());
+            }
+            value = Some(v);
+            Ok(())
+        })?;
+        value.map(Cell::new).ok_or_else(|| {
+            SnapshotError::Malformed {
+                offset: 0,
+                reason: "cell snapshot has no value".to_owned(),
+            }
+            .into()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::{collection::vec, prelude::any, proptest};
+
+    fn snapshot(storage: &Storage<u64, String>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        Storage::write_snapshot(&storage.view(), &mut bytes).expect("write to vec can't fail");
+        bytes
+    }
+
+    #[test]
+    fn storage_roundtrip() {
+        // Enough entries to span several chunks
+        let storage = (0..20_000)
+            .map(|i| (i, i.to_string()))
+            .collect::<Storage<u64, String>>();
+
+        storage.block().commit_with(7_u64);
+
+        let bytes = snapshot(&storage);
+        let loaded =
+            Storage::<u64, String>::read_snapshot(bytes.as_slice()).expect("valid snapshot");
+
+        assert!(loaded.view().iter().eq(storage.view().iter()));
+        // Metadata is decoded as the type requested first
+        assert_eq!(loaded.view().meta::<u64>(), Some(&7));
+        assert_eq!(loaded.view().meta::<u32>(), None);
+    }
+
+    #[test]
+    fn snapshot_drops_revert_and_redo() {
+        let storage = Storage::<u64, String>::new();
+        {
+            let mut block = storage.block();
+            block.insert(0, "0".to_owned());
+            block.commit();
+        }
+        storage.block_and_revert().commit();
+
+        let loaded = Storage::<u64, String>::read_snapshot(snapshot(&storage).as_slice())
+            .expect("valid snapshot");
+        assert!(matches!(
+            loaded.block_and_redo(),
+            Err(storage::RedoError::NotReverted)
+        ));
+        assert_eq!(loaded.block_and_revert().reverted(), Reverted::Nothing);
+    }
+
+    #[test]
+    fn cell_roundtrip() {
+        let cell = Cell::new("value".to_owned());
+
+        let mut bytes = Vec::new();
+        Cell::write_snapshot(&cell.view(), &mut bytes).expect("write to vec can't fail");
+        let loaded = Cell::<String>::read_snapshot(bytes.as_slice()).expect("valid snapshot");
+
+        assert_eq!(loaded.view().get(), "value");
+        // Snapshot of the cell is not a storage snapshot
+        assert!(matches!(
+            Storage::<u64, String>::read_snapshot(bytes.as_slice()),
+            Err(Error::Snapshot(SnapshotError::Malformed { offset: 12, .. }))
+        ));
+    }
+
+    #[test]
+    fn corruption_offset() {
+        let storage = Storage::<u64, String>::from_iter([(0, "a".to_owned())]);
+        let bytes = snapshot(&storage);
+        // Header with empty metadata is 22 bytes long and followed by the first chunk
+        let chunk = 22;
+
+        let mut corrupted = bytes.clone();
+        corrupted[chunk + 4] ^= 1;
+        assert!(matches!(
+            Storage::<u64, String>::read_snapshot(corrupted.as_slice()),
+            Err(Error::Snapshot(SnapshotError::Checksum { offset })) if offset == chunk as u64
+        ));
+
+        let mut corrupted = bytes.clone();
+        let digest = corrupted.len() - 4;
+        corrupted[digest] ^= 1;
+        assert!(matches!(
+            Storage::<u64, String>::read_snapshot(corrupted.as_slice()),
+            Err(Error::Snapshot(SnapshotError::Digest { offset })) if offset == digest as u64
+        ));
+
+        let truncated = &bytes[..bytes.len() - 2];
+        let Err(Error::Snapshot(error)) = Storage::<u64, String>::read_snapshot(truncated) else {
+            panic!("truncated snapshot must be rejected");
+        };
+        assert_eq!(error.offset(), truncated.len() as u64);
+    }
+
+    #[test]
+    fn incremental() {
+        let mut storage = (0..10)
+            .map(|i| (i, i.to_string()))
+            .collect::<Storage<u64, String>>();
+        // Changes are tracked only on request
+        assert!(matches!(
+            storage.export_incremental(0, &mut Vec::new()),
+            Err(Error::Io(error)) if error.kind() == io::ErrorKind::InvalidInput
+        ));
+        storage.track_changes();
+        {
+            let mut block = storage.block();
+            block.insert(10, "10".to_owned());
+            block.commit();
+        }
+
+        let base = snapshot(&storage);
+        let base_version = storage.version();
+
+        {
+            let mut block = storage.block();
+            block.insert(0, "changed".to_owned());
+            block.remove(1);
+            block.commit();
+        }
+        {
+            let mut block = storage.block();
+            *block.get_mut(&2).expect("inserted above") = "mutated".to_owned();
+            block.insert(11, "11".to_owned());
+            block.commit_with(7_u64);
+        }
+
+        let mut incremental = Vec::new();
+        storage
+            .export_incremental(base_version, &mut incremental)
+            .expect("write to vec can't fail");
+        // Only changed keys are written
+        let mut full = Vec::new();
+        Storage::write_snapshot(&storage.view(), &mut full).expect("write to vec can't fail");
+        assert!(incremental.len() < full.len());
+
+        let restored =
+            Storage::<u64, String>::read_snapshot(base.as_slice()).expect("valid snapshot");
+        assert_eq!(restored.version(), base_version);
+        // Changes before the restored snapshot are unknown
+        assert!(restored
+            .export_incremental(base_version - 1, &mut Vec::new())
+            .is_err());
+
+        restored
+            .import_incremental(incremental.as_slice())
+            .expect("valid incremental snapshot");
+        assert!(restored.view().iter().eq(storage.view().iter()));
+        assert_eq!(restored.version(), storage.version());
+        // Metadata of the exported version is attached
+        assert_eq!(restored.view().meta::<u64>(), Some(&7));
+
+        // Incremental snapshot can't be applied twice
+        assert!(matches!(
+            restored.import_incremental(incremental.as_slice()),
+            Err(Error::Snapshot(SnapshotError::Base { base, .. })) if base == base_version
+        ));
+
+        // Snapshot without changes keeps the storage as is
+        let mut empty = Vec::new();
+        storage
+            .export_incremental(storage.version(), &mut empty)
+            .expect("write to vec can't fail");
+        let version = restored.version();
+        restored
+            .import_incremental(empty.as_slice())
+            .expect("valid incremental snapshot");
+        assert_eq!(restored.version(), version);
+        assert!(matches!(
+            restored.block_and_revert().reverted(),
+            Reverted::Block { version: reverted, keys } if reverted == version && keys > 0
+        ));
+
+        // Changes exceeding quota are rejected without changing the storage
+        let mut limited =
+            Storage::<u64, String>::read_snapshot(base.as_slice()).expect("valid snapshot");
+        limited.set_quota(Quota::new().max_entries(10));
+        assert!(matches!(
+            limited.import_incremental(incremental.as_slice()),
+            Err(Error::Snapshot(SnapshotError::Quota { .. }))
+        ));
+        assert_eq!(limited.version(), base_version);
+        assert!(limited.view().get(&0).is_some_and(|value| value == "0"));
+
+        // Pruned changes are forgotten, later ones are kept
+        storage.prune_changes(base_version + 1);
+        let modified = storage.modified.as_ref().expect("changes are tracked");
+        assert_eq!(modified.read().len(), 2);
+        assert!(storage
+            .export_incremental(base_version, &mut Vec::new())
+            .is_err());
+        storage
+            .export_incremental(base_version + 1, &mut Vec::new())
+            .expect("write to vec can't fail");
+    }
+
+    #[test]
+    fn pending_block() {
+        let storage = (0..4)
+            .map(|i| (i, i.to_string()))
+            .collect::<Storage<u64, String>>();
+        {
+            let mut block = storage.block();
+            block.insert(0, "changed".to_owned());
+            block.commit();
+        }
+        let expected = {
+            let mut block = storage.block_and_revert();
+            *block.get_mut(&1).expect("inserted above") = "mutated".to_owned();
+            block.remove(2);
+            block.insert(4, "4".to_owned());
+            block
+                .iter()
+                .map(|(k, v)| (*k, v.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        let mut pending = Vec::new();
+        {
+            let mut block = storage.block_and_revert();
+            *block.get_mut(&1).expect("inserted above") = "mutated".to_owned();
+            block.remove(2);
+            block.insert(4, "4".to_owned());
+            block
+                .write_pending(&mut pending)
+                .expect("write to vec can't fail");
+            // Block is dropped without commit
+        }
+
+        let block = storage
+            .resume_block(pending.as_slice())
+            .expect("valid pending block");
+        block.commit();
+        assert!(storage
+            .view()
+            .iter()
+            .map(|(k, v)| (*k, v.clone()))
+            .eq(expected));
+
+        // Resumed revert can't be reverted, but can be redone
+        assert_eq!(storage.block_and_revert().reverted(), Reverted::Nothing);
+        storage
+            .block_and_redo()
+            .expect("resumed block is a revert")
+            .commit();
+        assert_eq!(storage.view().get(&0), Some(&"changed".to_owned()));
+
+        // Storage moved past the base of the pending block
+        assert!(matches!(
+            storage.resume_block(pending.as_slice()),
+            Err(Error::Snapshot(SnapshotError::Base { .. }))
+        ));
+    }
+
+    #[test]
+    fn pending_block_state() {
+        let mut storage = (0..4)
+            .map(|i| (i, i.to_string()))
+            .collect::<Storage<u64, String>>();
+        let initial = snapshot(&storage);
+
+        // Revert map of the resumed block is restored
+        let mut pending = Vec::new();
+        {
+            let mut block = storage.block();
+            block.insert(0, "changed".to_owned());
+            block.remove(1);
+            block
+                .write_pending(&mut pending)
+                .expect("write to vec can't fail");
+        }
+        storage
+            .resume_block(pending.as_slice())
+            .expect("valid pending block")
+            .commit();
+        storage.block_and_revert().commit();
+        let initial =
+            Storage::<u64, String>::read_snapshot(initial.as_slice()).expect("valid snapshot");
+        assert!(storage.view().iter().eq(initial.view().iter()));
+
+        // Revert of nothing keeps redo of the previous revert
+        let mut pending = Vec::new();
+        {
+            let block = storage.block_and_revert();
+            assert_eq!(block.reverted(), Reverted::Nothing);
+            block
+                .write_pending(&mut pending)
+                .expect("write to vec can't fail");
+        }
+        storage
+            .resume_block(pending.as_slice())
+            .expect("valid pending block")
+            .commit();
+        storage
+            .block_and_redo()
+            .expect("revert of nothing keeps redo")
+            .commit();
+        assert_eq!(storage.view().get(&0), Some(&"changed".to_owned()));
+
+        // Writes are replayed with quota checks
+        let mut pending = Vec::new();
+        {
+            let mut block = storage.block();
+            block.insert(4, "4".to_owned());
+            block.insert(5, "5".to_owned());
+            block
+                .write_pending(&mut pending)
+                .expect("write to vec can't fail");
+        }
+        storage.set_quota(Quota::new().max_entries(4));
+        assert!(matches!(
+            storage.resume_block(pending.as_slice()),
+            Err(Error::Snapshot(SnapshotError::Quota { .. }))
+        ));
+    }
+
+    proptest! {
+        #[test]
+        fn incremental_consistent_with_full(blocks in vec(vec(any::<(u8, Option<u64>)>(), 0..16), 0..16), base: usize) {
+            let mut storage = Storage::<u8, u64>::new();
+            storage.track_changes();
+            let base = base % (blocks.len() + 1);
+            let mut restored = None;
+
+            for (i, ops) in blocks.into_iter().enumerate() {
+                if i == base {
+                    restored = Some(snapshot_of(&storage));
+                }
+                let mut block = storage.block();
+                for (key, value) in ops {
+                    match value {
+                        Some(value) => block.insert(key, value),
+                        None => block.remove(key),
+                    };
+                }
+                block.commit();
+            }
+            let restored = restored.unwrap_or_else(|| snapshot_of(&storage));
+            let restored = Storage::<u8, u64>::read_snapshot(restored.as_slice()).expect("valid snapshot");
+
+            let mut incremental = Vec::new();
+            storage.export_incremental(restored.version(), &mut incremental).expect("write to vec can't fail");
+            restored.import_incremental(incremental.as_slice()).expect("valid incremental snapshot");
+
+            assert!(restored.view().iter().eq(storage.view().iter()));
+        }
+    }
+
+    fn snapshot_of(storage: &Storage<u8, u64>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        Storage::write_snapshot(&storage.view(), &mut bytes).expect("write to vec can't fail");
+        bytes
+    }
+
+    proptest! {
+        #[test]
+        fn any_corruption_detected(entries: Vec<(u64, String)>, position: usize, flip in 1..=u8::MAX) {
+            let storage = entries.into_iter().collect::<Storage<u64, String>>();
+            let mut bytes = snapshot(&storage);
+            let position = position % bytes.len();
+            bytes[position] ^= flip;
+
+            assert!(Storage::<u64, String>::read_snapshot(bytes.as_slice()).is_err());
+        }
+    }
+}
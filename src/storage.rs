@@ -1,11 +1,25 @@
-use std::{borrow::Borrow, collections::BTreeMap, ops::RangeBounds};
+use core::{any::Any, fmt};
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, BTreeSet},
+    ops::RangeBounds,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+};
 
 use concread::{
     bptree::{BptreeMap, BptreeMapReadTxn, BptreeMapWriteTxn},
-    ebrcell::{EbrCell, EbrCellWriteTxn},
+    ebrcell::{EbrCell, EbrCellReadTxn, EbrCellWriteTxn},
 };
 
-use crate::{Key, Value};
+use crate::{
+    codec::{Decode, Encode},
+    index::{ErasedIndex, ErasedIndexRead, Index, IndexData, IndexView, Indexes},
+    version::Version,
+    Error, Key, Value,
+};
 
 /// Multi-version key value storage
 pub struct Storage<K: Key, V: Value> {
@@ -13,6 +27,233 @@ pub struct Storage<K: Key, V: Value> {
     pub(crate) revert: EbrCell<BTreeMap<K, Option<V>>>,
     /// Map which represent aggregated changes of multiple blocks
     pub(crate) blocks: BptreeMap<K, V>,
+    /// Secondary indexes over values in the `blocks` map
+    pub(crate) indexes: Vec<Box<dyn ErasedIndex<K, V>>>,
+    /// Version incremented by every commit, readers retry while commit is published
+    pub(crate) version: Version,
+    /// Version at which each key was last written, removed keys are kept as tombstones,
+    /// tracked only after [`Storage::track_changes`]
+    pub(crate) modified: Option<BptreeMap<K, u64>>,
+    /// Version since which `modified` is tracked, earlier changes are unknown
+    pub(crate) tracked_since: AtomicU64,
+    /// Values of the latest reverted block, required to redo the revert
+    pub(crate) redo: EbrCell<Redo<BTreeMap<K, Option<V>>>>,
+    /// Whether `revert` holds changes of the latest committed block
+    pub(crate) revertible: AtomicBool,
+    /// Receipts of transactions applied in the latest committed block
+    pub(crate) receipts: EbrCell<Vec<Receipt<K, V>>>,
+    /// Metadata attached to the latest committed block
+    pub(crate) meta: EbrCell<Meta>,
+    /// Capacity limits enforced on inserts
+    pub(crate) quota: Quota<K, V>,
+    /// Estimated size of committed entries, tracked only with size quota
+    pub(crate) bytes: AtomicUsize,
+}
+
+/// Storage which keeps values behind [`Arc`]
+///
+/// Previous versions of values in revert maps share allocation with the stored value,
+/// so use `make_mut` to clone value only when it's about to be mutated.
+pub type ArcStorage<K, V> = Storage<K, Arc<V>>;
+
+/// Metadata attached to the block by [`Block::commit_with`]
+pub(crate) type Meta = Option<Arc<BlockMeta>>;
+
+/// Type erased metadata of the block which is kept encoded, so that it's persisted by snapshots
+pub struct BlockMeta {
+    pub(crate) bytes: Vec<u8>,
+    /// Value block was committed with, or value decoded from `bytes` on the first access after load
+    value: OnceLock<Box<dyn Any + Send + Sync>>,
+}
+
+/// Marker of metadata which can't be decoded as the requested type
+struct Undecodable;
+
+impl BlockMeta {
+    pub(crate) fn new<M: Any + Send + Sync + Encode>(meta: M) -> Self {
+        let mut bytes = Vec::new();
+        meta.encode(&mut bytes).expect("write to vec can't fail");
+        Self {
+            bytes,
+            value: OnceLock::from(Box::new(meta) as Box<dyn Any + Send + Sync>),
+        }
+    }
+
+    /// Metadata loaded from the snapshot
+    pub(crate) fn encoded(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes,
+            value: OnceLock::new(),
+        }
+    }
+
+    fn get<M: Any + Send + Sync + Decode>(&self) -> Option<&M> {
+        self.value
+            .get_or_init(|| {
+                let mut bytes = self.bytes.as_slice();
+                match M::decode(&mut bytes) {
+                    Ok(meta) if bytes.is_empty() => Box::new(meta),
+                    _ => Box::new(Undecodable),
+                }
+            })
+            .downcast_ref()
+    }
+}
+
+/// Changes undone by the latest revert
+#[derive(Clone, Default)]
+pub(crate) struct Redo<T> {
+    /// Version at which revert was committed, `None` if there is nothing to redo
+    pub(crate) version: Option<u64>,
+    /// Values which were replaced by the revert
+    pub(crate) changes: T,
+}
+
+/// Error which might occur when redoing reverted block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedoError {
+    /// No block was reverted since the latest redo
+    NotReverted,
+    /// Blocks were committed after the revert
+    Outdated {
+        /// Version at which revert was committed
+        reverted: u64,
+        /// Current version of the storage
+        version: u64,
+    },
+}
+
+impl fmt::Display for RedoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotReverted => write!(f, "no reverted block to redo"),
+            Self::Outdated { reverted, version } => write!(
+                f,
+                "block reverted at version {reverted} can't be redone at version {version}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RedoError {}
+
+/// Outcome of the revert performed by `block_and_revert`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reverted {
+    /// There was no committed block to revert
+    Nothing,
+    /// Changes of the latest committed block were reverted
+    Block {
+        /// Version of the reverted block
+        version: u64,
+        /// Amount of entries restored by the revert
+        keys: usize,
+    },
+}
+
+/// Error returned by strict revert when there is no committed block to revert
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NothingToRevert;
+
+impl fmt::Display for NothingToRevert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no committed block to revert")
+    }
+}
+
+impl std::error::Error for NothingToRevert {}
+
+/// Estimate of the entry size used by the size quota
+pub(crate) type SizeFn<K, V> = Box<dyn Fn(&K, &V) -> usize + Send + Sync>;
+
+/// Capacity limits of the storage enforced on inserts of [`Block`] and [`Transaction`]
+///
+/// Removals, reverts and values mutated in place with `get_mut` are accounted but never rejected.
+pub struct Quota<K, V> {
+    pub(crate) max_entries: Option<usize>,
+    pub(crate) max_bytes: Option<(usize, SizeFn<K, V>)>,
+}
+
+impl<K, V> Quota<K, V> {
+    /// Construct quota without limits
+    pub fn new() -> Self {
+        Self {
+            max_entries: None,
+            max_bytes: None,
+        }
+    }
+
+    /// Limit amount of entries in the storage
+    pub fn max_entries(mut self, max: usize) -> Self {
+        self.max_entries = Some(max);
+        self
+    }
+
+    /// Limit estimated size of entries in the storage, `size` estimates size of the single entry
+    pub fn max_bytes(
+        mut self,
+        max: usize,
+        size: impl Fn(&K, &V) -> usize + Send + Sync + 'static,
+    ) -> Self {
+        self.max_bytes = Some((max, Box::new(size)));
+        self
+    }
+
+    /// Estimated size of the entry, zero without size quota
+    pub(crate) fn size(&self, key: &K, value: Option<&V>) -> usize {
+        match (&self.max_bytes, value) {
+            (Some((_, size)), Some(value)) => size(key, value),
+            _ => 0,
+        }
+    }
+}
+
+impl<K, V> Default for Quota<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Limit of the storage which insert would exceed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaExceeded {
+    /// Maximum amount of entries
+    Entries {
+        /// Configured limit
+        limit: usize,
+    },
+    /// Maximum estimated size of entries
+    Bytes {
+        /// Configured limit
+        limit: usize,
+        /// Estimated size of entries after the insert
+        size: usize,
+    },
+}
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Entries { limit } => write!(f, "amount of entries would exceed quota of {limit}"),
+            Self::Bytes { limit, size } => write!(
+                f,
+                "estimated size {size} would exceed quota of {limit} bytes"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+impl<T> Redo<T> {
+    /// Check that revert is the latest commit
+    pub(crate) fn check(&self, version: u64) -> Result<(), RedoError> {
+        match self.version {
+            None => Err(RedoError::NotReverted),
+            Some(reverted) if reverted != version => Err(RedoError::Outdated { reverted, version }),
+            Some(_) => Ok(()),
+        }
+    }
 }
 
 impl<K: Key, V: Value> Storage<K, V> {
@@ -21,43 +262,263 @@ impl<K: Key, V: Value> Storage<K, V> {
         Self {
             revert: EbrCell::new(BTreeMap::new()),
             blocks: BptreeMap::new(),
+            indexes: Vec::new(),
+            version: Version::new(0),
+            modified: None,
+            tracked_since: AtomicU64::new(0),
+            redo: EbrCell::new(Redo::default()),
+            revertible: AtomicBool::new(false),
+            receipts: EbrCell::new(Vec::new()),
+            meta: EbrCell::new(None),
+            quota: Quota::new(),
+            bytes: AtomicUsize::new(0),
+        }
+    }
+
+    /// Get amount of blocks committed to the storage
+    pub fn version(&self) -> u64 {
+        self.version.get()
+    }
+
+    /// Declare secondary index on values extracted by `extractor`
+    ///
+    /// Index is built from the existing entries and maintained by every subsequent write.
+    pub fn add_index<I: Key>(
+        &mut self,
+        extractor: impl Fn(&V) -> I + Send + Sync + 'static,
+    ) -> Index<I, K, V> {
+        let index = IndexData::new(Box::new(extractor), self.blocks.read().iter());
+        let id = index.id();
+        self.indexes.push(Box::new(index));
+        Index {
+            position: self.indexes.len() - 1,
+            id,
+            _marker: core::marker::PhantomData,
+        }
+    }
+
+    /// Set capacity limits enforced on subsequent inserts
+    ///
+    /// Existing entries are kept even if they exceed the limits.
+    pub fn set_quota(&mut self, quota: Quota<K, V>) {
+        let bytes = self
+            .blocks
+            .read()
+            .iter()
+            .map(|(key, value)| quota.size(key, Some(value)))
+            .sum();
+        *self.bytes.get_mut() = bytes;
+        self.quota = quota;
+    }
+
+    /// Start tracking versions at which keys are written, required by [`Storage::export_incremental`]
+    ///
+    /// Changes are tracked from the current version, tracking is kept until the storage is dropped.
+    pub fn track_changes(&mut self) {
+        if self.modified.is_none() {
+            self.modified = Some(BptreeMap::new());
+            *self.tracked_since.get_mut() = self.version.get_mut();
         }
     }
 
+    /// Forget changes made at or before `base_version`
+    ///
+    /// Call with the oldest version consumers of incremental snapshots are at,
+    /// so that tracking keeps only keys written after it.
+    pub fn prune_changes(&self, base_version: u64) {
+        let Some(modified) = &self.modified else {
+            return;
+        };
+        let mut modified = modified.write();
+        let base_version = base_version.min(self.version());
+        // Raise the bound before entries are removed, so exports never miss removed changes
+        self.tracked_since
+            .fetch_max(base_version, Ordering::Relaxed);
+        let stale = modified
+            .iter()
+            .filter(|(_, changed)| **changed <= base_version)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in &stale {
+            modified.remove(key);
+        }
+        modified.commit();
+    }
+
+    /// Estimated size of committed entries, always zero without size quota
+    pub fn bytes(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
     /// Create persistent view of storage at certain point in time
     pub fn view(&self) -> View<'_, K, V> {
+        // Retry if commit is published while read transactions are opened
+        self.version.read(|version| self.view_at(version))
+    }
+
+    /// Create view at the version observed by the caller
+    pub(crate) fn view_at(&self, version: u64) -> View<'_, K, V> {
         View {
             blocks: self.blocks.read(),
+            indexes: self.indexes.iter().map(|index| index.read()).collect(),
+            receipts: self.receipts.read(),
+            meta: self.meta.read(),
+            modified: self.modified.as_ref().map(BptreeMap::read),
+            // Loaded after the read of `modified`, so it covers every pruned change
+            tracked_since: self.tracked_since.load(Ordering::Relaxed),
+            version,
         }
     }
 
     /// Create block to aggregate updates
     pub fn block(&self) -> Block<'_, K, V> {
         let mut revert = self.revert.write();
+        let mut redo = self.redo.write();
+        let mut receipts = self.receipts.write();
+        receipts.get_mut().clear();
         let blocks = self.blocks.write();
+        let indexes = Indexes::write(&self.indexes);
+        let modified = Modified {
+            map: self.modified.as_ref().map(BptreeMap::write),
+            version: self.version() + 1,
+        };
 
-        // Clear revert
+        // Clear revert and keep version of the redo to report it
         revert.get_mut().clear();
-
-        Block { revert, blocks }
+        redo.get_mut().changes.clear();
+
+        Block {
+            revert,
+            prev: BTreeMap::new(),
+            transactions: 0,
+            redo,
+            receipts,
+            blocks,
+            indexes,
+            modified,
+            usage: Usage::new(&self.quota, &self.bytes),
+            version: &self.version,
+            meta: &self.meta,
+            reverted: Reverted::Nothing,
+            revertible: &self.revertible,
+            keep_revert: true,
+            carry_redo: false,
+        }
     }
 
     /// Create block to aggregate updates and revert changes created in the latest block
+    ///
+    /// Reverted changes are kept, so they can be restored with [`Self::block_and_redo`].
+    /// What was reverted is reported by [`Block::reverted`], reverting twice in a row reverts nothing.
+    /// Block which reverts nothing leaves the storage as is and keeps changes undone by the previous revert,
+    /// so they can still be redone unless the block writes.
+    /// Metadata of the reverted version is not restored, revert is a new version with metadata it's committed with.
     pub fn block_and_revert(&self) -> Block<'_, K, V> {
         let mut revert = self.revert.write();
-        let mut blocks = self.blocks.write();
+        let mut redo = self.redo.write();
+        let mut receipts = self.receipts.write();
+        receipts.get_mut().clear();
+        let blocks = self.blocks.write();
+        let indexes = Indexes::write(&self.indexes);
+        let modified = Modified {
+            map: self.modified.as_ref().map(BptreeMap::write),
+            version: self.version() + 1,
+        };
+
+        let prev = core::mem::take(revert.get_mut());
+        let revertible = self.revertible.load(Ordering::Relaxed);
+        let (reverted, prev) = if revertible {
+            *redo.get_mut() = Redo {
+                version: Some(modified.version),
+                changes: BTreeMap::new(),
+            };
+            let reverted = Reverted::Block {
+                version: self.version(),
+                keys: prev.len(),
+            };
+            (reverted, prev)
+        } else {
+            // Changes left in revert by the previous revert block are not reverted
+            (Reverted::Nothing, BTreeMap::new())
+        };
+        let mut block = Block {
+            revert,
+            prev: BTreeMap::new(),
+            transactions: 0,
+            redo,
+            receipts,
+            blocks,
+            indexes,
+            modified,
+            usage: Usage::new(&self.quota, &self.bytes),
+            version: &self.version,
+            meta: &self.meta,
+            reverted,
+            revertible: &self.revertible,
+            keep_revert: false,
+            carry_redo: !revertible,
+        };
+        for (key, value) in prev {
+            let next = block.write(key.clone(), value);
+            block.redo.get_mut().changes.insert(key, next);
+        }
 
-        {
-            let revert = core::mem::take(revert.get_mut());
-            for (key, value) in revert {
-                match value {
-                    None => blocks.remove(&key),
-                    Some(value) => blocks.insert(key, value),
-                };
-            }
+        block
+    }
+
+    /// Same as [`Self::block_and_revert`] but fails if there is no committed block to revert
+    pub fn try_block_and_revert(&self) -> Result<Block<'_, K, V>, NothingToRevert> {
+        let block = self.block_and_revert();
+        match block.reverted {
+            Reverted::Nothing => Err(NothingToRevert),
+            Reverted::Block { .. } => Ok(block),
+        }
+    }
+
+    /// Create block to aggregate updates and restore changes undone by the latest revert
+    ///
+    /// Fails if the latest committed block is not a revert.
+    /// Redo survives `serde` snapshots, but not binary snapshots of a view.
+    pub fn block_and_redo(&self) -> Result<Block<'_, K, V>, RedoError> {
+        let mut revert = self.revert.write();
+        let mut redo = self.redo.write();
+        let mut receipts = self.receipts.write();
+        receipts.get_mut().clear();
+        let version = self.version();
+        redo.check(version)?;
+        let blocks = self.blocks.write();
+        let indexes = Indexes::write(&self.indexes);
+        let modified = Modified {
+            map: self.modified.as_ref().map(BptreeMap::write),
+            version: version + 1,
+        };
+
+        revert.get_mut().clear();
+        let next = core::mem::take(redo.get_mut());
+        let mut block = Block {
+            revert,
+            prev: BTreeMap::new(),
+            transactions: 0,
+            redo,
+            receipts,
+            blocks,
+            indexes,
+            modified,
+            usage: Usage::new(&self.quota, &self.bytes),
+            version: &self.version,
+            meta: &self.meta,
+            reverted: Reverted::Nothing,
+            revertible: &self.revertible,
+            keep_revert: true,
+            carry_redo: false,
+        };
+        // Redo can be reverted again
+        for (key, value) in next.changes {
+            let prev = block.write(key.clone(), value);
+            block.prev.insert(key, Prev::new(prev));
         }
 
-        Block { revert, blocks }
+        Ok(block)
     }
 }
 
@@ -72,6 +533,16 @@ impl<K: Key, V: Value> FromIterator<(K, V)> for Storage<K, V> {
         Self {
             revert: EbrCell::new(BTreeMap::new()),
             blocks: iter.into_iter().collect(),
+            indexes: Vec::new(),
+            version: Version::new(0),
+            modified: None,
+            tracked_since: AtomicU64::new(0),
+            redo: EbrCell::new(Redo::default()),
+            revertible: AtomicBool::new(false),
+            receipts: EbrCell::new(Vec::new()),
+            meta: EbrCell::new(None),
+            quota: Quota::new(),
+            bytes: AtomicUsize::new(0),
         }
     }
 }
@@ -94,6 +565,11 @@ pub trait StorageReadOnly<K: Key, V: Value> {
 
     /// Get amount of entries in the storage
     fn len(&self) -> usize;
+
+    /// Check if storage has no entries
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 /// Module for [`View`] and it's related impls
@@ -102,6 +578,51 @@ mod view {
     /// Consistent view of the storage at the certain version
     pub struct View<'storage, K: Key, V: Value> {
         pub(crate) blocks: BptreeMapReadTxn<'storage, K, V>,
+        pub(crate) indexes: Vec<Box<dyn ErasedIndexRead<K> + 'storage>>,
+        pub(crate) receipts: EbrCellReadTxn<Vec<Receipt<K, V>>>,
+        pub(crate) meta: EbrCellReadTxn<Meta>,
+        /// Versions at which keys were last written, `None` unless changes are tracked
+        pub(crate) modified: Option<BptreeMapReadTxn<'storage, K, u64>>,
+        /// Version since which `modified` is complete
+        pub(crate) tracked_since: u64,
+        pub(crate) version: u64,
+    }
+
+    impl<'storage, K: Key, V: Value> View<'storage, K, V> {
+        /// Amount of blocks committed to the storage when view was created
+        pub fn version(&self) -> u64 {
+            self.version
+        }
+
+        /// Receipts of transactions applied in the latest block committed before view was created
+        pub fn receipts(&self) -> &[Receipt<K, V>] {
+            &self.receipts
+        }
+
+        /// Metadata attached to the latest block committed before view was created
+        ///
+        /// `None` if block was committed without metadata or metadata has another type.
+        /// Metadata loaded from the snapshot is decoded as the type requested first.
+        pub fn meta<M: Any + Send + Sync + Decode>(&self) -> Option<&M> {
+            self.meta.as_deref()?.get()
+        }
+
+        /// Get view of the secondary index at the same version as this view
+        ///
+        /// # Panics
+        ///
+        /// If index belongs to another storage.
+        pub fn index<I: Key>(&self, index: &Index<I, K, V>) -> IndexView<'_, I, K> {
+            let read = self
+                .indexes
+                .get(index.position)
+                .filter(|read| read.id() == index.id)
+                .expect("index handle belongs to another storage");
+            IndexView {
+                index: &**read,
+                _marker: core::marker::PhantomData,
+            }
+        }
     }
 
     impl<K: Key, V: Value> StorageReadOnly<K, V> for View<'_, K, V> {
@@ -134,6 +655,32 @@ mod view {
             self.blocks.len()
         }
     }
+
+    impl<'storage, K: Key, V: Value + PartialEq> View<'storage, K, V> {
+        /// Iterate over differences between `self` and `other` views of the same storage ordered by key
+        ///
+        /// `self` is treated as older version and `other` as newer, see [`Diff`] for the complexity.
+        pub fn diff<'slf>(&'slf self, other: &'slf View<'storage, K, V>) -> Diff<'slf, K, V> {
+            let walk = match &other.modified {
+                // Every change after the older view is tracked
+                Some(modified)
+                    if other.tracked_since <= self.version && self.version <= other.version =>
+                {
+                    Walk::Tracked {
+                        changed: modified.iter(),
+                        since: self.version,
+                        old: &self.blocks,
+                        new: &other.blocks,
+                    }
+                }
+                _ => Walk::Merge {
+                    old: self.iter().peekable(),
+                    new: other.iter().peekable(),
+                },
+            };
+            Diff { walk }
+        }
+    }
 }
 pub use view::View;
 
@@ -144,7 +691,24 @@ mod block {
     /// Batched update to the storage that can be reverted later
     pub struct Block<'store, K: Key, V: Value> {
         pub(crate) revert: EbrCellWriteTxn<'store, BTreeMap<K, Option<V>>>,
+        /// Values of keys before the block, published to `revert` on commit
+        pub(crate) prev: BTreeMap<K, Prev<V>>,
+        /// Amount of transactions created by the block, used as transaction id
+        pub(crate) transactions: u64,
+        pub(crate) redo: EbrCellWriteTxn<'store, Redo<BTreeMap<K, Option<V>>>>,
+        pub(crate) receipts: EbrCellWriteTxn<'store, Vec<Receipt<K, V>>>,
         pub(crate) blocks: BptreeMapWriteTxn<'store, K, V>,
+        pub(crate) indexes: Indexes<'store, K, V>,
+        pub(crate) modified: Modified<'store, K>,
+        pub(crate) usage: Usage<'store, K, V>,
+        pub(crate) version: &'store Version,
+        pub(crate) meta: &'store EbrCell<Meta>,
+        pub(crate) reverted: Reverted,
+        pub(crate) revertible: &'store AtomicBool,
+        /// Whether block could be reverted after commit
+        pub(crate) keep_revert: bool,
+        /// Whether block reverted nothing, so redo of the previous revert is kept unless block writes
+        pub(crate) carry_redo: bool,
     }
 
     impl<'store, K: Key, V: Value> Block<'store, K, V> {
@@ -153,42 +717,293 @@ mod block {
         where
             'store: 'block,
         {
+            self.flush_indexes();
+            self.transactions += 1;
             Transaction {
+                id: self.transactions,
                 block: self,
-                revert: BTreeMap::new(),
+                undo: UndoLog::new(),
             }
         }
 
         /// Apply aggregated changes to the storage
         pub fn commit(self) {
+            self.commit_meta(None);
+        }
+
+        /// Same as [`Self::commit`], currently never fails and exists for symmetry with fallible writes
+        pub fn try_commit(self) -> Result<(), Error> {
+            self.commit();
+            Ok(())
+        }
+
+        /// Apply aggregated changes to the storage and attach metadata to the resulting version
+        ///
+        /// Metadata is available with [`View::meta`] and persisted by snapshots of the version.
+        /// Metadata belongs to the committed version only: neither subsequent blocks nor reverts keep it,
+        /// so revert block has metadata it's committed with.
+        pub fn commit_with<M: Any + Send + Sync + Encode>(self, meta: M) {
+            self.commit_meta(Some(Arc::new(BlockMeta::new(meta))));
+        }
+
+        pub(crate) fn commit_meta(mut self, meta: Meta) {
+            self.prepare();
+            let mut publish = self.version.publish();
+            self.publish(publish.version(), meta);
+        }
+
+        /// Finish work which might panic before the block is published
+        pub(crate) fn prepare(&mut self) {
+            self.flush_indexes();
+        }
+
+        /// Publish prepared block while the caller marks the version as being published
+        pub(crate) fn publish(mut self, version: &mut u64, meta: Meta) {
+            debug_assert!(self.revert.is_empty(), "revert is cleared by block");
+            if self.carry_redo {
+                let redo = self.redo.get_mut();
+                if self.prev.is_empty() && redo.version == Some(*version) {
+                    redo.version = Some(self.modified.version);
+                } else {
+                    redo.changes.clear();
+                }
+            }
+            let prev = core::mem::take(&mut self.prev);
+            // Keys are sorted, so map is built in bulk
+            *self.revert.get_mut() = prev
+                .into_iter()
+                .map(|(key, prev)| (key, prev.value))
+                .collect();
+            let mut meta_write = self.meta.write();
+            *meta_write.get_mut() = meta;
             // Commit fields in the inverse order
+            meta_write.commit();
+            if let Some(modified) = self.modified.map {
+                modified.commit();
+            }
+            self.indexes.commit();
             self.blocks.commit();
+            self.redo.commit();
+            self.receipts.commit();
+            self.revertible.store(self.keep_revert, Ordering::Relaxed);
+            self.usage
+                .committed
+                .store(self.usage.bytes, Ordering::Relaxed);
             self.revert.commit();
+            *version = self.modified.version;
+        }
+
+        /// What was reverted when the block was created
+        pub fn reverted(&self) -> Reverted {
+            self.reverted
+        }
+
+        /// Restore values changed by the transaction of the receipt
+        ///
+        /// Fails if restored value exceeds quota of the storage, values restored before the failure are kept.
+        pub fn undo_receipt(&mut self, receipt: &Receipt<K, V>) -> Result<(), Error> {
+            for change in receipt.changes.iter().rev() {
+                match &change.pre {
+                    Some(value) => self.try_insert(change.key.clone(), value.clone())?,
+                    None => self.try_remove(change.key.clone())?,
+                };
+            }
+            Ok(())
         }
 
         /// Get mutable access to the value stored in
         pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
-            self.blocks.get_mut(key).map(|value| {
-                self.revert
+            value_mut(
+                &mut self.blocks,
+                &mut self.indexes,
+                &mut self.modified,
+                &mut self.usage,
+                key,
+            )
+            .inspect(|value| {
+                self.prev
                     .entry(key.clone())
-                    .or_insert_with(|| Some(value.clone()));
-                value
+                    .or_insert_with(|| Prev::new(Some((*value).clone())));
             })
         }
 
         /// Insert key value into the storage
+        ///
+        /// # Panics
+        ///
+        /// If [`Self::try_insert`] fails.
         pub fn insert(&mut self, key: K, value: V) -> Option<V> {
-            let prev_value = self.blocks.insert(key.clone(), value);
-            self.revert.entry(key).or_insert_with(|| prev_value.clone());
-            prev_value
+            self.try_insert(key, value).expect("insert failed")
+        }
+
+        /// Same as [`Self::insert`] but returns error instead of panicking
+        pub fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, Error> {
+            self.check_quota(&key, &value)?;
+            let prev_value = self.write(key.clone(), Some(value));
+            self.prev
+                .entry(key)
+                .or_insert_with(|| Prev::new(prev_value.clone()));
+            Ok(prev_value)
         }
 
         /// Remove key value from storage
         pub fn remove(&mut self, key: K) -> Option<V> {
-            let prev_value = self.blocks.remove(&key);
-            self.revert.entry(key).or_insert_with(|| prev_value.clone());
+            let prev_value = self.write(key.clone(), None);
+            self.prev
+                .entry(key)
+                .or_insert_with(|| Prev::new(prev_value.clone()));
+            prev_value
+        }
+
+        /// Same as [`Self::remove`], currently never fails and exists for symmetry with [`Self::try_insert`]
+        pub fn try_remove(&mut self, key: K) -> Result<Option<V>, Error> {
+            Ok(self.remove(key))
+        }
+
+        /// Set value of the key (or remove it in case of `None`) keeping indexes up to date, return previous value
+        pub(crate) fn write(&mut self, key: K, value: Option<V>) -> Option<V> {
+            let prev_value = match value {
+                None => self.blocks.remove(&key),
+                Some(value) => self.blocks.insert(key.clone(), value),
+            };
+            if !self.indexes.is_empty() {
+                self.indexes
+                    .update(&key, prev_value.as_ref(), self.blocks.get(&key));
+            }
+            self.usage
+                .replace(&key, prev_value.as_ref(), self.blocks.get(&key));
+            self.modified.mark(&key);
             prev_value
         }
+
+        /// Recompute index entries and sizes of values which were mutably borrowed
+        pub(crate) fn flush_indexes(&mut self) {
+            for key in self.indexes.take_dirty() {
+                self.indexes.update(&key, None, self.blocks.get(&key));
+            }
+            self.usage.flush(&self.blocks);
+        }
+
+        /// Check that insert of the value stays within quota of the storage
+        pub(crate) fn check_quota(&mut self, key: &K, value: &V) -> Result<(), QuotaExceeded> {
+            self.usage.flush(&self.blocks);
+            self.usage
+                .check(self.blocks.len(), key, self.blocks.get(key), value)
+        }
+    }
+
+    /// Get mutable access to the value, index entries and size of the value are recomputed on the next flush
+    fn value_mut<'a, K: Key, V: Value>(
+        blocks: &'a mut BptreeMapWriteTxn<'_, K, V>,
+        indexes: &mut Indexes<'_, K, V>,
+        modified: &mut Modified<'_, K>,
+        usage: &mut Usage<'_, K, V>,
+        key: &K,
+    ) -> Option<&'a mut V> {
+        let value = blocks.get_mut(key)?;
+        if !indexes.is_empty() {
+            indexes.mark_dirty(key, value);
+        }
+        usage.borrow(key, value);
+        modified.mark(key);
+        Some(value)
+    }
+
+    /// Estimated size of entries for the size quota
+    pub(crate) struct Usage<'store, K: Key, V: Value> {
+        pub(crate) quota: &'store Quota<K, V>,
+        /// Size of committed entries, updated on commit
+        pub(crate) committed: &'store AtomicUsize,
+        /// Size of entries including changes of the block
+        pub(crate) bytes: usize,
+        /// Mutably borrowed keys which size is excluded from `bytes` until the next flush
+        pub(crate) resized: BTreeSet<K>,
+    }
+
+    impl<'store, K: Key, V: Value> Usage<'store, K, V> {
+        pub(crate) fn new(quota: &'store Quota<K, V>, committed: &'store AtomicUsize) -> Self {
+            Self {
+                quota,
+                committed,
+                bytes: committed.load(Ordering::Relaxed),
+                resized: BTreeSet::new(),
+            }
+        }
+
+        /// Account replacement of the value of the key
+        fn replace(&mut self, key: &K, prev: Option<&V>, next: Option<&V>) {
+            if self.quota.max_bytes.is_none() {
+                return;
+            }
+            // Size of the borrowed value is already excluded
+            if !self.resized.remove(key) {
+                self.bytes = self.bytes.saturating_sub(self.quota.size(key, prev));
+            }
+            self.bytes += self.quota.size(key, next);
+        }
+
+        /// Exclude size of the value which is about to be mutably borrowed
+        fn borrow(&mut self, key: &K, value: &V) {
+            if self.quota.max_bytes.is_some() && self.resized.insert(key.clone()) {
+                self.bytes = self.bytes.saturating_sub(self.quota.size(key, Some(value)));
+            }
+        }
+
+        /// Add sizes of mutably borrowed values back
+        fn flush(&mut self, blocks: &BptreeMapWriteTxn<'_, K, V>) {
+            for key in core::mem::take(&mut self.resized) {
+                self.bytes += self.quota.size(&key, blocks.get(&key));
+            }
+        }
+
+        /// Check that replacing `prev` value of the key with `value` stays within quota
+        fn check(
+            &self,
+            len: usize,
+            key: &K,
+            prev: Option<&V>,
+            value: &V,
+        ) -> Result<(), QuotaExceeded> {
+            if let Some(limit) = self.quota.max_entries {
+                if prev.is_none() && len >= limit {
+                    return Err(QuotaExceeded::Entries { limit });
+                }
+            }
+            if let Some((limit, _)) = self.quota.max_bytes {
+                let size = self.bytes.saturating_sub(self.quota.size(key, prev))
+                    + self.quota.size(key, Some(value));
+                if size > limit {
+                    return Err(QuotaExceeded::Bytes { limit, size });
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Versions at which keys were last written
+    pub(crate) struct Modified<'store, K: Key> {
+        /// `None` unless storage tracks changes
+        pub(crate) map: Option<BptreeMapWriteTxn<'store, K, u64>>,
+        /// Version storage will have after the block is committed
+        pub(crate) version: u64,
+    }
+
+    impl<K: Key> Modified<'_, K> {
+        fn mark(&mut self, key: &K) {
+            if let Some(map) = &mut self.map {
+                // Lookup is cheaper than insert which copies the node on write
+                if map.get(key) != Some(&self.version) {
+                    map.insert(key.clone(), self.version);
+                }
+            }
+        }
+    }
+
+    impl<K: Key, V: Value> Block<'_, K, Arc<V>> {
+        /// Get mutable access to the value stored in, value is cloned only if it's shared
+        pub fn make_mut(&mut self, key: &K) -> Option<&mut V> {
+            self.get_mut(key).map(Arc::make_mut)
+        }
     }
 
     impl<K: Key, V: Value> StorageReadOnly<K, V> for Block<'_, K, V> {
@@ -223,41 +1038,93 @@ mod block {
 
     /// Part of block's aggregated changes which applied or aborted at the same time
     pub struct Transaction<'block, 'store, K: Key, V: Value> {
-        pub(crate) revert: BTreeMap<K, Option<V>>,
+        pub(crate) undo: UndoLog<K, V>,
+        /// Id of the transaction unique within the block
+        pub(crate) id: u64,
         pub(crate) block: &'block mut Block<'store, K, V>,
     }
 
     impl<'block, 'store: 'block, K: Key, V: Value> Transaction<'block, 'store, K, V> {
         /// Apply aggregated changes of [`Transaction`] to the [`Block`]
         pub fn apply(mut self) {
-            for (key, value) in core::mem::take(&mut self.revert) {
-                self.block.revert.entry(key).or_insert(value);
+            self.block.flush_indexes();
+            // Previous values of the block are updated by the writes, so log is simply discarded
+            self.undo.take();
+        }
+
+        /// Apply transaction and record it's changes as [`Receipt`] with the given id
+        ///
+        /// Receipts of the latest committed block are available with [`View::receipts`].
+        pub fn apply_with_receipt(mut self, id: u64) {
+            self.block.flush_indexes();
+            let mut changes = Vec::new();
+            for undo in self.undo.take() {
+                let (key, pre) = match undo {
+                    Undo::First(key) => {
+                        let pre = self.block.prev.get(&key).map(|prev| prev.value.clone());
+                        (key, pre.expect("first write is recorded in the block"))
+                    }
+                    Undo::Write(key, pre) => (key, pre),
+                };
+                let post = self.block.blocks.get(&key).cloned();
+                changes.push(Change { key, pre, post });
             }
+            self.block.receipts.push(Receipt { id, changes });
         }
 
         /// Get mutable access to the value stored in
         pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
-            self.block.blocks.get_mut(key).map(|value| {
-                self.revert
-                    .entry(key.clone())
-                    .or_insert_with(|| Some(value.clone()));
-                value
+            value_mut(
+                &mut self.block.blocks,
+                &mut self.block.indexes,
+                &mut self.block.modified,
+                &mut self.block.usage,
+                key,
+            )
+            .inspect(|value| {
+                self.undo.record(&mut self.block.prev, self.id, key, || {
+                    Some((*value).clone())
+                })
             })
         }
 
         /// Insert key value into the transaction temporary map
+        ///
+        /// # Panics
+        ///
+        /// If [`Self::try_insert`] fails.
         pub fn insert(&mut self, key: K, value: V) -> Option<V> {
-            let prev_value = self.block.blocks.insert(key.clone(), value);
-            self.revert.entry(key).or_insert_with(|| prev_value.clone());
-            prev_value
+            self.try_insert(key, value).expect("insert failed")
+        }
+
+        /// Same as [`Self::insert`] but returns error instead of panicking
+        pub fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, Error> {
+            self.block.check_quota(&key, &value)?;
+            let prev_value = self.block.write(key.clone(), Some(value));
+            self.undo
+                .record(&mut self.block.prev, self.id, &key, || prev_value.clone());
+            Ok(prev_value)
         }
 
         /// Remove key value from storage
         pub fn remove(&mut self, key: K) -> Option<V> {
-            let prev_value = self.block.blocks.remove(&key);
-            self.revert.entry(key).or_insert_with(|| prev_value.clone());
+            let prev_value = self.block.write(key.clone(), None);
+            self.undo
+                .record(&mut self.block.prev, self.id, &key, || prev_value.clone());
             prev_value
         }
+
+        /// Same as [`Self::remove`], currently never fails and exists for symmetry with [`Self::try_insert`]
+        pub fn try_remove(&mut self, key: K) -> Result<Option<V>, Error> {
+            Ok(self.remove(key))
+        }
+    }
+
+    impl<K: Key, V: Value> Transaction<'_, '_, K, Arc<V>> {
+        /// Get mutable access to the value stored in, value is cloned only if it's shared
+        pub fn make_mut(&mut self, key: &K) -> Option<&mut V> {
+            self.get_mut(key).map(Arc::make_mut)
+        }
     }
 
     impl<K: Key, V: Value> StorageReadOnly<K, V> for Transaction<'_, '_, K, V> {
@@ -290,27 +1157,152 @@ mod block {
         fn drop(&mut self) {
             // revert changes made so far by current transaction
             // if transaction was applied set would be empty
-            for (key, value) in core::mem::take(&mut self.revert) {
-                match value {
-                    None => self.block.blocks.remove(&key),
-                    Some(value) => self.block.blocks.insert(key, value),
+            for undo in self.undo.take().into_iter().rev() {
+                let (key, value) = match undo {
+                    Undo::First(key) => {
+                        let prev = self.block.prev.remove(&key);
+                        (
+                            key,
+                            prev.expect("first write is recorded in the block").value,
+                        )
+                    }
+                    Undo::Write(key, value) => (key, value),
                 };
+                self.block.write(key, value);
+            }
+        }
+    }
+
+    /// Changes made by the single transaction
+    #[derive(Debug, Clone)]
+    pub struct Receipt<K, V> {
+        pub(crate) id: u64,
+        pub(crate) changes: Vec<Change<K, V>>,
+    }
+
+    impl<K, V> Receipt<K, V> {
+        /// Id supplied when transaction was applied
+        pub fn id(&self) -> u64 {
+            self.id
+        }
+
+        /// Changes of the transaction in the order keys were first written
+        pub fn changes(&self) -> &[Change<K, V>] {
+            &self.changes
+        }
+    }
+
+    /// Value of the key before and after the transaction
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Change<K, V> {
+        pub(crate) key: K,
+        pub(crate) pre: Option<V>,
+        pub(crate) post: Option<V>,
+    }
+
+    impl<K, V> Change<K, V> {
+        /// Changed key
+        pub fn key(&self) -> &K {
+            &self.key
+        }
+
+        /// Value before the transaction, `None` if key was absent
+        pub fn pre(&self) -> Option<&V> {
+            self.pre.as_ref()
+        }
+
+        /// Value after the transaction, `None` if key was removed
+        pub fn post(&self) -> Option<&V> {
+            self.post.as_ref()
+        }
+    }
+
+    /// Value of the key before the block
+    pub(crate) struct Prev<V> {
+        pub(crate) value: Option<V>,
+        /// Id of the latest transaction which wrote the key, zero if it wasn't written by transaction
+        pub(crate) transaction: u64,
+    }
+
+    impl<V> Prev<V> {
+        pub(crate) fn new(value: Option<V>) -> Self {
+            Self {
+                value,
+                transaction: 0,
             }
         }
     }
+
+    /// Write recorded by the transaction undo log
+    pub(crate) enum Undo<K, V> {
+        /// The first write of the key in the block, previous value is kept by the block
+        First(K),
+        /// The first write of the key in the transaction which was already written in the block
+        Write(K, Option<V>),
+    }
+
+    /// Append-only log of the first writes of keys made by the transaction
+    ///
+    /// Writes are deduplicated against previous values of the block which are tagged with the id
+    /// of the latest transaction writing the key, so recording costs a single lookup and repeated
+    /// writes of the key clone nothing. Previous values of the block are updated right away,
+    /// so applying transaction only discards the log, while abort restores values in the reverse order.
+    pub(crate) struct UndoLog<K: Key, V: Value> {
+        entries: Vec<Undo<K, V>>,
+    }
+
+    impl<K: Key, V: Value> UndoLog<K, V> {
+        pub(crate) fn new() -> Self {
+            Self {
+                entries: Vec::new(),
+            }
+        }
+
+        /// Record previous value of the key unless it was already written by the transaction
+        pub(crate) fn record(
+            &mut self,
+            prev: &mut BTreeMap<K, Prev<V>>,
+            transaction: u64,
+            key: &K,
+            prev_value: impl FnOnce() -> Option<V>,
+        ) {
+            match prev.get_mut(key) {
+                Some(prev) if prev.transaction == transaction => {}
+                Some(prev) => {
+                    prev.transaction = transaction;
+                    self.entries.push(Undo::Write(key.clone(), prev_value()));
+                }
+                None => {
+                    let value = Prev {
+                        value: prev_value(),
+                        transaction,
+                    };
+                    prev.insert(key.clone(), value);
+                    self.entries.push(Undo::First(key.clone()));
+                }
+            }
+        }
+
+        /// Take recorded writes in the order they were made
+        pub(crate) fn take(&mut self) -> Vec<Undo<K, V>> {
+            core::mem::take(&mut self.entries)
+        }
+    }
 }
-pub use block::{Block, Transaction};
+pub(crate) use block::Prev;
+pub use block::{Block, Change, Receipt, Transaction};
+use block::{Modified, Usage};
 mod iter {
     use super::*;
 
     /// Iterate over entries in block, view or transaction
     pub struct Iter<'slf, K: Key, V: Value> {
-        pub(crate) iter: concread::internals::bptree::iter::Iter<'slf, 'slf, K, V>,
+        pub(crate) iter: concread::internals::bptree::iter::Iter<'slf, K, V>,
     }
 
     /// Iterate over range of entries in block, view or transaction
     pub struct RangeIter<'slf, K: Key, V: Value> {
-        pub(crate) iter: concread::internals::bptree::iter::RangeIter<'slf, 'slf, K, V>,
+        pub(crate) iter: concread::internals::bptree::iter::RangeIter<'slf, K, V>,
     }
 
     impl<'slf, K: Key, V: Value> Iterator for Iter<'slf, K, V> {
@@ -331,6 +1323,127 @@ mod iter {
 }
 pub use iter::{Iter, RangeIter};
 
+/// Module for [`Diff`] and it's related impls
+mod diff {
+    use core::iter::Peekable;
+
+    use super::*;
+
+    /// Difference of the single entry between two views
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum DiffEntry<'slf, K, V> {
+        /// Entry is present only in the newer view
+        Added(&'slf K, &'slf V),
+        /// Entry is present only in the older view
+        Removed(&'slf K, &'slf V),
+        /// Entry is present in both views but with different values: key, old value, new value
+        Changed(&'slf K, &'slf V, &'slf V),
+    }
+
+    impl<'slf, K, V> DiffEntry<'slf, K, V> {
+        /// Key of the changed entry
+        pub fn key(&self) -> &'slf K {
+            match self {
+                Self::Added(key, _) | Self::Removed(key, _) | Self::Changed(key, _, _) => key,
+            }
+        }
+    }
+
+    /// Iterate over differences between two views ordered by key
+    ///
+    /// If storage tracks changes (see [`Storage::track_changes`]) since the older view was created,
+    /// only keys written after it are looked up in both views, so diff takes time linear in the amount
+    /// of the tracked keys. Otherwise both views are walked entry by entry, so diff takes time linear
+    /// in the size of the views even if only few entries changed: nodes of the underlying tree aren't
+    /// exposed by `concread`, so unchanged subtrees can't be skipped. Values which have the same address
+    /// (e.g. located in the leaf shared by both versions) are considered equal without calling [`PartialEq`].
+    pub struct Diff<'slf, K: Key, V: Value> {
+        pub(crate) walk: Walk<'slf, K, V>,
+    }
+
+    /// Way the views are compared
+    pub(crate) enum Walk<'slf, K: Key, V: Value> {
+        /// Both views are merged entry by entry
+        Merge {
+            old: Peekable<Iter<'slf, K, V>>,
+            new: Peekable<Iter<'slf, K, V>>,
+        },
+        /// Keys written after version `since` are looked up in both views
+        Tracked {
+            changed: concread::internals::bptree::iter::Iter<'slf, K, u64>,
+            since: u64,
+            old: &'slf BptreeMapReadTxn<'slf, K, V>,
+            new: &'slf BptreeMapReadTxn<'slf, K, V>,
+        },
+    }
+
+    /// Same address means that value is located in the leaf shared by both versions
+    fn same<V: PartialEq>(old: &V, new: &V) -> bool {
+        core::ptr::eq(old, new) || old == new
+    }
+
+    impl<'slf, K: Key, V: Value + PartialEq> Iterator for Diff<'slf, K, V> {
+        type Item = DiffEntry<'slf, K, V>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            let (old, new) = match &mut self.walk {
+                Walk::Merge { old, new } => (old, new),
+                Walk::Tracked {
+                    changed,
+                    since,
+                    old,
+                    new,
+                } => {
+                    return changed.filter(|(_, changed)| **changed > *since).find_map(
+                        |(key, _)| match (old.get(key), new.get(key)) {
+                            (None, None) => None,
+                            (Some(old), None) => Some(DiffEntry::Removed(key, old)),
+                            (None, Some(new)) => Some(DiffEntry::Added(key, new)),
+                            (Some(old), Some(new)) => {
+                                (!same(old, new)).then_some(DiffEntry::Changed(key, old, new))
+                            }
+                        },
+                    );
+                }
+            };
+            loop {
+                let entry = match (old.peek(), new.peek()) {
+                    (None, None) => return None,
+                    (Some(_), None) => {
+                        let (key, value) = old.next()?;
+                        DiffEntry::Removed(key, value)
+                    }
+                    (None, Some(_)) => {
+                        let (key, value) = new.next()?;
+                        DiffEntry::Added(key, value)
+                    }
+                    (Some((old_key, _)), Some((new_key, _))) => match old_key.cmp(new_key) {
+                        core::cmp::Ordering::Less => {
+                            let (key, value) = old.next()?;
+                            DiffEntry::Removed(key, value)
+                        }
+                        core::cmp::Ordering::Greater => {
+                            let (key, value) = new.next()?;
+                            DiffEntry::Added(key, value)
+                        }
+                        core::cmp::Ordering::Equal => {
+                            let (key, old_value) = old.next()?;
+                            let (_, new_value) = new.next()?;
+                            if same(old_value, new_value) {
+                                continue;
+                            }
+                            DiffEntry::Changed(key, old_value, new_value)
+                        }
+                    },
+                };
+                return Some(entry);
+            }
+        }
+    }
+}
+use diff::Walk;
+pub use diff::{Diff, DiffEntry};
+
 #[cfg(test)]
 mod tests {
     use std::{
@@ -340,7 +1453,7 @@ mod tests {
 
     use super::*;
 
-    use proptest::proptest;
+    use proptest::{collection::vec, prelude::any, proptest};
 
     #[test]
     fn get() {
@@ -424,8 +1537,8 @@ mod tests {
         // Check that aborted transaction changes don't visible for subsequent transactions
         {
             let transaction = block.transaction();
-            assert_eq!(transaction.get(&0).as_deref().copied(), Some(0));
-            assert_eq!(transaction.get(&1).as_deref().copied(), None);
+            assert_eq!(transaction.get(&0).copied(), Some(0));
+            assert_eq!(transaction.get(&1).copied(), None);
         }
 
         block.commit();
@@ -433,8 +1546,8 @@ mod tests {
         // Check that effect of aborted step is not visible in the storage after committing transaction
         {
             let view = storage.view();
-            assert_eq!(view.get(&0).as_deref().copied(), Some(0));
-            assert_eq!(view.get(&1).as_deref().copied(), None);
+            assert_eq!(view.get(&0).copied(), Some(0));
+            assert_eq!(view.get(&1).copied(), None);
         }
     }
 
@@ -615,6 +1728,447 @@ mod tests {
         assert_eq!(view2.get(&0), Some(&0));
     }
 
+    #[test]
+    fn redo() {
+        let storage = Storage::<u64, u64>::from_iter([(0, 0), (1, 1)]);
+        let entries = |storage: &Storage<u64, u64>| {
+            storage
+                .view()
+                .iter()
+                .map(|(k, v)| (*k, *v))
+                .collect::<Vec<_>>()
+        };
+        let base = entries(&storage);
+
+        {
+            let mut block = storage.block();
+            block.insert(0, 10);
+            block.remove(1);
+            block.insert(2, 2);
+            block.commit();
+        }
+        let changed = entries(&storage);
+        assert_eq!(storage.block_and_redo().err(), Some(RedoError::NotReverted));
+
+        // Revert and redo can be repeated back and forth
+        for _ in 0..2 {
+            storage.block_and_revert().commit();
+            assert_eq!(entries(&storage), base);
+            storage.block_and_redo().expect("reverted above").commit();
+            assert_eq!(entries(&storage), changed);
+        }
+        // Redo is consumed by commit
+        assert_eq!(storage.block_and_redo().err(), Some(RedoError::NotReverted));
+
+        // Aborted revert doesn't allow redo
+        drop(storage.block_and_revert());
+        assert!(storage.block_and_redo().is_err());
+
+        storage.block_and_revert().commit();
+        storage.block().commit();
+        assert_eq!(
+            storage.block_and_redo().err(),
+            Some(RedoError::Outdated {
+                reverted: storage.version() - 1,
+                version: storage.version()
+            })
+        );
+    }
+
+    #[test]
+    fn revert_twice_keeps_redo() {
+        let storage = Storage::<u64, u64>::from_iter([(0, 0)]);
+        {
+            let mut block = storage.block();
+            block.insert(0, 1);
+            block.commit();
+        }
+
+        // Revert of nothing leaves the previous revert redoable
+        storage.block_and_revert().commit();
+        let block = storage.block_and_revert();
+        assert_eq!(block.reverted(), Reverted::Nothing);
+        block.commit();
+        assert_eq!(storage.view().get(&0), Some(&0));
+        storage.block_and_redo().expect("reverted above").commit();
+        assert_eq!(storage.view().get(&0), Some(&1));
+
+        // Writes of the revert block are not reverted by the next revert
+        {
+            let mut block = storage.block_and_revert();
+            block.insert(5, 5);
+            block.commit();
+        }
+        let block = storage.block_and_revert();
+        assert_eq!(block.reverted(), Reverted::Nothing);
+        block.commit();
+        assert_eq!(storage.view().get(&5), Some(&5));
+        assert_eq!(storage.view().get(&0), Some(&0));
+
+        // Revert of nothing which writes discards redo
+        {
+            let mut block = storage.block_and_revert();
+            block.insert(6, 6);
+            block.commit();
+        }
+        assert!(storage.block_and_redo().is_err());
+        assert_eq!(storage.view().get(&0), Some(&0));
+    }
+
+    #[test]
+    fn revert_outcome() {
+        let storage = Storage::<u64, u64>::from_iter([(0, 0)]);
+        assert_eq!(storage.block_and_revert().reverted(), Reverted::Nothing);
+        assert_eq!(storage.try_block_and_revert().err(), Some(NothingToRevert));
+
+        {
+            let mut block = storage.block();
+            block.insert(0, 1);
+            block.insert(1, 1);
+            block.commit();
+        }
+        {
+            let block = storage
+                .try_block_and_revert()
+                .expect("block committed above");
+            assert_eq!(
+                block.reverted(),
+                Reverted::Block {
+                    version: 1,
+                    keys: 2
+                }
+            );
+            block.commit();
+        }
+        // Revert can't be reverted
+        let block = storage.block_and_revert();
+        assert_eq!(block.reverted(), Reverted::Nothing);
+        drop(block);
+        assert!(storage.try_block_and_revert().is_err());
+
+        // Redo can be reverted
+        storage.block_and_redo().expect("reverted above").commit();
+        assert_eq!(
+            storage.block_and_revert().reverted(),
+            Reverted::Block {
+                version: 3,
+                keys: 2
+            }
+        );
+
+        // Empty block is reverted as well
+        storage.block().commit();
+        assert_eq!(
+            storage.block_and_revert().reverted(),
+            Reverted::Block {
+                version: 4,
+                keys: 0
+            }
+        );
+    }
+
+    #[test]
+    fn receipts() {
+        let storage = Storage::<u64, u64>::from_iter([(0, 0)]);
+        let change = |key, pre, post| Change { key, pre, post };
+
+        {
+            let mut block = storage.block();
+
+            let mut transaction = block.transaction();
+            transaction.insert(1, 1);
+            *transaction.get_mut(&0).expect("inserted above") = 5;
+            transaction.apply_with_receipt(7);
+
+            let mut transaction = block.transaction();
+            transaction.remove(1);
+            transaction.insert(2, 2);
+            transaction.insert(2, 3);
+            transaction.apply_with_receipt(8);
+
+            // Transaction applied without receipt isn't recorded
+            let mut transaction = block.transaction();
+            transaction.insert(4, 4);
+            transaction.apply();
+
+            block.commit();
+        }
+
+        let view = storage.view();
+        let receipts = view.receipts();
+        assert_eq!(receipts.len(), 2);
+        assert_eq!(receipts[0].id(), 7);
+        assert_eq!(
+            receipts[0].changes(),
+            [change(1, None, Some(1)), change(0, Some(0), Some(5))]
+        );
+        assert_eq!(receipts[1].id(), 8);
+        assert_eq!(
+            receipts[1].changes(),
+            [change(1, Some(1), None), change(2, None, Some(3))]
+        );
+
+        // Undo effects of the single transaction
+        {
+            let mut block = storage.block();
+            block.undo_receipt(&receipts[1]).expect("quota is not set");
+            block.commit();
+        }
+        let undone = storage.view();
+        assert_eq!(
+            undone.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>(),
+            [(0, 5), (1, 1), (4, 4)]
+        );
+        // Receipts are kept only for the latest block
+        assert!(undone.receipts().is_empty());
+        assert_eq!(view.receipts().len(), 2);
+    }
+
+    #[test]
+    fn meta() {
+        #[derive(Debug, PartialEq)]
+        struct Header {
+            height: u64,
+        }
+
+        impl Encode for Header {
+            fn encode<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+                self.height.encode(writer)
+            }
+        }
+
+        impl Decode for Header {
+            fn decode<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+                u64::decode(reader).map(|height| Self { height })
+            }
+        }
+
+        let storage = Storage::<u64, u64>::new();
+        assert_eq!(storage.view().meta::<Header>(), None);
+
+        storage.block().commit_with(Header { height: 1 });
+        let view1 = storage.view();
+        storage.block().commit();
+        let view2 = storage.view();
+        storage.block().commit_with(Header { height: 3 });
+
+        assert_eq!(view1.meta(), Some(&Header { height: 1 }));
+        // Metadata of another type isn't returned
+        assert_eq!(view1.meta::<u64>(), None);
+        // Block committed without metadata
+        assert_eq!(view2.meta::<Header>(), None);
+        assert_eq!(storage.view().meta(), Some(&Header { height: 3 }));
+
+        // Revert doesn't restore metadata of the reverted version
+        storage.block_and_revert().commit();
+        assert_eq!(storage.view().meta::<Header>(), None);
+        storage
+            .block_and_redo()
+            .expect("reverted above")
+            .commit_with(Header { height: 5 });
+        assert_eq!(storage.view().meta(), Some(&Header { height: 5 }));
+    }
+
+    #[test]
+    fn fallible_writes() {
+        let storage = Storage::<u64, u64>::new();
+
+        let run = || -> Result<(), Error> {
+            let mut block = storage.block();
+            assert_eq!(block.try_insert(0, 0)?, None);
+            let mut transaction = block.transaction();
+            assert_eq!(transaction.try_insert(0, 1)?, Some(0));
+            assert_eq!(transaction.try_remove(1)?, None);
+            transaction.apply();
+            assert_eq!(block.try_remove(0)?, Some(1));
+            block.try_commit()?;
+            storage.try_block_and_revert()?.try_commit()?;
+            // Revert can't be reverted
+            storage.try_block_and_revert()?.try_commit()
+        };
+        assert!(matches!(
+            run(),
+            Err(Error::NothingToRevert(NothingToRevert))
+        ));
+        assert_eq!(storage.version(), 2);
+    }
+
+    #[test]
+    fn quota() {
+        let mut storage = Storage::<u64, String>::from_iter([(0, "aa".to_owned())]);
+        storage.set_quota(
+            Quota::new()
+                .max_entries(3)
+                .max_bytes(10, |_, value: &String| value.len()),
+        );
+        assert_eq!(storage.bytes(), 2);
+
+        {
+            let mut block = storage.block();
+            block.insert(1, "bbbb".to_owned());
+            assert!(matches!(
+                block.try_insert(2, "cccccc".to_owned()),
+                Err(Error::Quota(QuotaExceeded::Bytes {
+                    limit: 10,
+                    size: 12
+                }))
+            ));
+            // Rejected insert doesn't change the block
+            assert_eq!(block.get(&2), None);
+            block.insert(2, "cc".to_owned());
+            assert!(matches!(
+                block.try_insert(3, String::new()),
+                Err(Error::Quota(QuotaExceeded::Entries { limit: 3 }))
+            ));
+            // Replacing existing entry doesn't add one
+            block.insert(0, "aaaa".to_owned());
+
+            // Size of the rolled back transaction is released
+            {
+                let mut transaction = block.transaction();
+                transaction.insert(1, "b".to_owned());
+                *transaction.get_mut(&0).expect("inserted above") = String::new();
+            }
+            assert!(block.try_insert(2, "ccc".to_owned()).is_err());
+
+            // Mutation in place is accounted
+            block.get_mut(&1).expect("inserted above").truncate(1);
+            block.insert(2, "ccc".to_owned());
+            block.commit();
+        }
+        assert_eq!(storage.bytes(), 8);
+
+        // Revert releases size of the reverted entries
+        storage.block_and_revert().commit();
+        assert_eq!(storage.bytes(), 2);
+        assert_eq!(storage.view().len(), 1);
+
+        // Undo of the receipt is checked against quota
+        {
+            let mut block = storage.block();
+            let mut transaction = block.transaction();
+            transaction.remove(0);
+            transaction.apply_with_receipt(0);
+            block.insert(1, "bbbbbbbbbb".to_owned());
+            block.commit();
+        }
+        let view = storage.view();
+        let mut block = storage.block();
+        assert!(matches!(
+            block.undo_receipt(&view.receipts()[0]),
+            Err(Error::Quota(QuotaExceeded::Bytes { limit: 10, .. }))
+        ));
+    }
+
+    #[test]
+    fn transaction_hot_key() {
+        let storage = Storage::<u64, u64>::from_iter([(0, 0)]);
+
+        let mut block = storage.block();
+
+        // Aborted transaction restores value before the first write
+        {
+            let mut transaction = block.transaction();
+            for i in 1..10 {
+                transaction.insert(0, i);
+                transaction.remove(0);
+                transaction.insert(1, i);
+            }
+        }
+        assert_eq!(block.get(&0), Some(&0));
+        assert_eq!(block.get(&1), None);
+
+        {
+            let mut transaction = block.transaction();
+            for i in 1..10 {
+                transaction.insert(0, i);
+                *transaction.get_mut(&0).expect("inserted above") += 1;
+            }
+            transaction.apply();
+        }
+        block.commit();
+
+        assert_eq!(storage.view().get(&0), Some(&10));
+
+        // Block revert restores value before the first transaction
+        storage.block_and_revert().commit();
+        assert_eq!(storage.view().get(&0), Some(&0));
+    }
+
+    #[test]
+    fn commit_is_atomic() {
+        const BLOCKS: u64 = 1_000;
+        const KEYS: u64 = 4;
+
+        let storage = (0..KEYS).map(|key| (key, 0)).collect::<Storage<u64, u64>>();
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for i in 1..=BLOCKS {
+                    let mut block = storage.block();
+                    for key in 0..KEYS {
+                        block.insert(key, i);
+                    }
+                    block.commit_with(i);
+                }
+            });
+
+            // Block `i` writes `i` to every key, so values and revert must match the version
+            loop {
+                let view = storage.view();
+                let version = view.version();
+                assert!(view.iter().all(|(_, value)| *value == version));
+                assert_eq!(view.meta::<u64>(), (version > 0).then_some(&version));
+
+                {
+                    let (revert, blocks) = storage
+                        .version
+                        .read(|_| (storage.revert.read(), storage.blocks.read()));
+                    let value = *blocks.get(&0).expect("key is present");
+                    assert!(blocks.iter().all(|(_, v)| *v == value));
+                    assert!(revert.values().all(|prev| *prev == Some(value - 1)));
+                }
+
+                if version == BLOCKS {
+                    break;
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn arc_make_mut() {
+        let storage = ArcStorage::<u64, Vec<u64>>::from_iter([(0, Arc::new(vec![0]))]);
+
+        let view0 = storage.view();
+
+        {
+            let mut block = storage.block();
+            block.make_mut(&0).expect("inserted above").push(1);
+            // Value is cloned once per block
+            let value = block.get(&0).map(Arc::as_ptr);
+            block.make_mut(&0).expect("inserted above").push(2);
+            assert_eq!(block.get(&0).map(Arc::as_ptr), value);
+            block.commit()
+        }
+
+        let view1 = storage.view();
+
+        storage.block_and_revert().commit();
+
+        let view2 = storage.view();
+
+        assert_eq!(
+            view1.get(&0).map(|value| value.as_slice()),
+            Some([0, 1, 2].as_slice())
+        );
+        // Reverted value is the same allocation as before the block
+        assert!(Arc::ptr_eq(
+            view0.get(&0).expect("inserted above"),
+            view2.get(&0).expect("reverted above")
+        ));
+    }
+
     #[test]
     fn len() {
         let storage = Storage::<u64, u64>::new();
@@ -647,6 +2201,94 @@ mod tests {
         assert_eq!(view.len(), 5);
     }
 
+    #[test]
+    fn diff() {
+        let storage = Storage::<u64, u64>::new();
+
+        {
+            let mut block = storage.block();
+            for (key, value) in [(0, 0), (1, 0), (2, 0)] {
+                block.insert(key, value);
+            }
+            block.commit()
+        }
+
+        let view1 = storage.view();
+
+        {
+            let mut block = storage.block();
+            block.insert(0, 1);
+            block.insert(3, 1);
+            block.remove(2);
+            // Overwrite with the same value is not a change
+            block.insert(1, 0);
+            block.commit()
+        }
+
+        let view2 = storage.view();
+
+        assert_eq!(
+            view1.diff(&view2).collect::<Vec<_>>(),
+            [
+                DiffEntry::Changed(&0, &0, &1),
+                DiffEntry::Removed(&2, &0),
+                DiffEntry::Added(&3, &1),
+            ]
+        );
+        assert_eq!(
+            view2.diff(&view1).collect::<Vec<_>>(),
+            [
+                DiffEntry::Changed(&0, &1, &0),
+                DiffEntry::Added(&2, &0),
+                DiffEntry::Removed(&3, &1),
+            ]
+        );
+        assert_eq!(view2.diff(&view2).count(), 0);
+    }
+
+    #[test]
+    fn diff_tracked() {
+        let untracked = Storage::<u64, u64>::from_iter((0..100).map(|i| (i, i)));
+        let mut storage = Storage::<u64, u64>::from_iter((0..100).map(|i| (i, i)));
+        storage.track_changes();
+
+        let write = |storage: &Storage<u64, u64>, round: u64| {
+            let mut block = storage.block();
+            block.insert(round, 0);
+            block.remove(round + 50);
+            block.insert(round + 100, round);
+            // Overwrite with the same value is not a change
+            block.insert(round + 10, round + 10);
+            block.commit();
+        };
+        for round in 0..3 {
+            write(&storage, round);
+            write(&untracked, round);
+        }
+        let (view0, untracked0) = (storage.view(), untracked.view());
+        for round in 3..6 {
+            write(&storage, round);
+            write(&untracked, round);
+        }
+        let (view1, untracked1) = (storage.view(), untracked.view());
+
+        // Only keys written after the older view are visited, result is the same as of the full walk
+        assert!(matches!(view0.diff(&view1).walk, Walk::Tracked { .. }));
+        assert!(view0.diff(&view1).eq(untracked0.diff(&untracked1)));
+        assert_eq!(view0.diff(&view1).count(), 9);
+        // Newer view as `self` falls back to the full walk
+        assert!(matches!(view1.diff(&view0).walk, Walk::Merge { .. }));
+        assert!(view1.diff(&view0).eq(untracked1.diff(&untracked0)));
+
+        // Changes before the older view are pruned, so they are unknown to the later views
+        storage.prune_changes(4);
+        let view2 = storage.view();
+        assert!(matches!(view0.diff(&view2).walk, Walk::Merge { .. }));
+        assert!(view0.diff(&view2).eq(untracked0.diff(&untracked1)));
+        assert!(matches!(view1.diff(&view2).walk, Walk::Tracked { .. }));
+        assert_eq!(view1.diff(&view2).count(), 0);
+    }
+
     proptest! {
         #[test]
         fn consistent_with_btreemap(txs: Vec<(bool, Vec<(u64, Option<u64>)>)>) {
@@ -689,5 +2331,133 @@ mod tests {
                 );
             }
         }
+
+        #[test]
+        fn transactions_consistent_with_btreemap(initial: Vec<(u8, u8)>, txs: Vec<(bool, Vec<(u8, Option<u8>)>)>) {
+            let storage = initial.iter().copied().collect::<Storage<u8, u8>>();
+            let base = initial.into_iter().collect::<BTreeMap<_, _>>();
+            let mut map = base.clone();
+
+            {
+                let mut block = storage.block();
+                for (applied, tx) in txs {
+                    let mut transaction = block.transaction();
+                    let mut changes = map.clone();
+                    for (key, value) in tx {
+                        match value {
+                            Some(value) if value % 3 == 0 => {
+                                if let Some(stored) = transaction.get_mut(&key) {
+                                    *stored = value;
+                                    changes.insert(key, value);
+                                }
+                            }
+                            Some(value) => {
+                                changes.insert(key, value);
+                                transaction.insert(key, value);
+                            }
+                            None => {
+                                changes.remove(&key);
+                                transaction.remove(key);
+                            }
+                        }
+                    }
+                    if applied {
+                        map = changes;
+                        transaction.apply();
+                    }
+                }
+                block.commit();
+            }
+            assert!(storage.view().iter().map(|(k, v)| (*k, *v)).eq(map));
+
+            // Previous values recorded by transactions restore the state before the block
+            storage.block_and_revert().commit();
+            assert!(storage.view().iter().map(|(k, v)| (*k, *v)).eq(base));
+        }
+
+        #[test]
+        fn diff_consistent_with_btreemap(
+            initial: Vec<(u64, u64)>,
+            tx: Vec<(u64, Option<u64>)>,
+            tracked: bool,
+        ) {
+            let mut storage = initial.iter().copied().collect::<Storage<u64, u64>>();
+            if tracked {
+                storage.track_changes();
+            }
+            let old = initial.into_iter().collect::<BTreeMap<_, _>>();
+            let mut new = old.clone();
+
+            let view_old = storage.view();
+            {
+                let mut block = storage.block();
+                for (key, value) in tx {
+                    match value {
+                        Some(value) => {
+                            new.insert(key, value);
+                            block.insert(key, value);
+                        }
+                        None => {
+                            new.remove(&key);
+                            block.remove(key);
+                        }
+                    }
+                }
+                block.commit();
+            }
+            let view_new = storage.view();
+
+            let keys = old.keys().chain(new.keys()).collect::<BTreeSet<_>>();
+            let expected = keys
+                .into_iter()
+                .filter_map(|key| match (old.get(key), new.get(key)) {
+                    (Some(old), Some(new)) if old != new => Some(DiffEntry::Changed(key, old, new)),
+                    (Some(old), None) => Some(DiffEntry::Removed(key, old)),
+                    (None, Some(new)) => Some(DiffEntry::Added(key, new)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+
+            assert_eq!(view_old.diff(&view_new).collect::<Vec<_>>(), expected);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn quota_accounting_consistent(blocks in vec((any::<bool>(), vec(any::<(u8, Option<u8>, u8)>(), 0..16)), 0..16)) {
+            let mut storage = Storage::<u8, String>::new();
+            storage.set_quota(Quota::new().max_bytes(usize::MAX, |_, value: &String| value.len()));
+
+            for (revert, ops) in blocks {
+                let mut block = if revert { storage.block_and_revert() } else { storage.block() };
+                for (key, len, mode) in ops {
+                    match (len, mode % 3) {
+                        (None, _) => {
+                            block.remove(key);
+                        }
+                        (Some(len), 0) => {
+                            block.insert(key, "a".repeat(len.into()));
+                        }
+                        (Some(len), 1) => {
+                            if let Some(value) = block.get_mut(&key) {
+                                *value = "b".repeat(len.into());
+                            }
+                        }
+                        // Aborted transaction
+                        (Some(len), _) => {
+                            let mut transaction = block.transaction();
+                            transaction.insert(key, "c".repeat(len.into()));
+                            if let Some(value) = transaction.get_mut(&key.wrapping_add(1)) {
+                                value.push('c');
+                            }
+                        }
+                    }
+                }
+                block.commit();
+
+                let expected = storage.view().iter().map(|(_, value)| value.len()).sum::<usize>();
+                assert_eq!(storage.bytes(), expected);
+            }
+        }
     }
 }
@@ -0,0 +1,568 @@
+//! Multi-version append-oriented vector
+
+use std::{collections::BTreeMap, ops::RangeBounds};
+
+use concread::{
+    bptree::{BptreeMap, BptreeMapReadTxn, BptreeMapWriteTxn},
+    ebrcell::{EbrCell, EbrCellWriteTxn},
+};
+
+use crate::Value;
+
+/// Multi-version vector
+pub struct StorageVec<V: Value> {
+    /// Previous length and overwritten items, required to perform revert of the latest changes
+    pub(crate) revert: EbrCell<Revert<V>>,
+    /// Items stored by their index, which represent aggregated changes of multiple blocks
+    pub(crate) blocks: BptreeMap<usize, V>,
+}
+
+/// Changes required to restore vector to the previous state
+#[derive(Clone)]
+pub(crate) struct Revert<V> {
+    /// Length before the changes, `None` if length is unchanged
+    pub(crate) len: Option<usize>,
+    /// Previous values of items which existed before the changes and were overwritten or removed
+    pub(crate) items: BTreeMap<usize, V>,
+}
+
+impl<V> Default for Revert<V> {
+    fn default() -> Self {
+        Self {
+            len: None,
+            items: BTreeMap::new(),
+        }
+    }
+}
+
+impl<V: Value> Revert<V> {
+    /// Length vector had before the changes, `current` is length at the moment
+    fn prev_len(&self, current: usize) -> usize {
+        self.len.unwrap_or(current)
+    }
+
+    /// Record length change, `current` is length before the change
+    fn record_len(&mut self, current: usize) {
+        self.len.get_or_insert(current);
+    }
+
+    /// Record value of the item before it's first overwritten since the changes began
+    fn record_item(&mut self, current_len: usize, index: usize, value: &V) {
+        if index < self.prev_len(current_len) {
+            self.items.entry(index).or_insert_with(|| value.clone());
+        }
+    }
+
+    /// Restore previous state of the `blocks`
+    fn revert(self, blocks: &mut BptreeMapWriteTxn<'_, usize, V>) {
+        if let Some(len) = self.len {
+            for index in (len..blocks.len()).rev() {
+                blocks.remove(&index);
+            }
+        }
+        for (index, value) in self.items {
+            blocks.insert(index, value);
+        }
+    }
+}
+
+impl<V: Value> StorageVec<V> {
+    /// Construct new [`Self`]
+    pub fn new() -> Self {
+        Self {
+            revert: EbrCell::new(Revert::default()),
+            blocks: BptreeMap::new(),
+        }
+    }
+
+    /// Create persistent view of vector at certain point in time
+    pub fn view(&self) -> View<'_, V> {
+        View {
+            blocks: self.blocks.read(),
+        }
+    }
+
+    /// Create block to aggregate updates
+    pub fn block(&self) -> Block<'_, V> {
+        let mut revert = self.revert.write();
+        let blocks = self.blocks.write();
+
+        // Clear revert
+        *revert.get_mut() = Revert::default();
+
+        Block { revert, blocks }
+    }
+
+    /// Create block to aggregate updates and revert changes created in the latest block
+    pub fn block_and_revert(&self) -> Block<'_, V> {
+        let mut revert = self.revert.write();
+        let mut blocks = self.blocks.write();
+
+        core::mem::take(revert.get_mut()).revert(&mut blocks);
+
+        Block { revert, blocks }
+    }
+}
+
+impl<V: Value> Default for StorageVec<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V: Value> FromIterator<V> for StorageVec<V> {
+    fn from_iter<I: IntoIterator<Item = V>>(iter: I) -> Self {
+        Self {
+            revert: EbrCell::new(Revert::default()),
+            blocks: iter.into_iter().enumerate().collect(),
+        }
+    }
+}
+
+pub trait StorageVecReadOnly<V: Value> {
+    /// Read item at the index
+    fn get(&self, index: usize) -> Option<&V>;
+
+    /// Iterate over all items in the vector
+    fn iter(&self) -> Iter<'_, V>;
+
+    /// Iterate over items with indexes in the range
+    fn range(&self, bounds: impl RangeBounds<usize>) -> Iter<'_, V>;
+
+    /// Get amount of items in the vector
+    fn len(&self) -> usize;
+
+    /// Check if vector has no items
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Read the last item
+    fn last(&self) -> Option<&V> {
+        self.len().checked_sub(1).and_then(|index| self.get(index))
+    }
+}
+
+/// Module for [`View`] and it's related impls
+mod view {
+    use super::*;
+    /// Consistent view of the vector at the certain version
+    pub struct View<'storage, V: Value> {
+        pub(crate) blocks: BptreeMapReadTxn<'storage, usize, V>,
+    }
+
+    impl<V: Value> StorageVecReadOnly<V> for View<'_, V> {
+        fn get(&self, index: usize) -> Option<&V> {
+            self.blocks.get(&index)
+        }
+
+        fn iter(&self) -> Iter<'_, V> {
+            Iter {
+                iter: self.blocks.range(..),
+            }
+        }
+
+        fn range(&self, bounds: impl RangeBounds<usize>) -> Iter<'_, V> {
+            Iter {
+                iter: self.blocks.range(bounds),
+            }
+        }
+
+        fn len(&self) -> usize {
+            self.blocks.len()
+        }
+    }
+}
+pub use view::View;
+
+/// Module for [`Block`] and it's related impls
+mod block {
+    use super::*;
+
+    /// Batched update to the vector that can be reverted later
+    pub struct Block<'store, V: Value> {
+        pub(crate) revert: EbrCellWriteTxn<'store, Revert<V>>,
+        pub(crate) blocks: BptreeMapWriteTxn<'store, usize, V>,
+    }
+
+    impl<'store, V: Value> Block<'store, V> {
+        /// Create transaction for the block
+        pub fn transaction<'block>(&'block mut self) -> Transaction<'block, 'store, V>
+        where
+            'store: 'block,
+        {
+            Transaction {
+                block: self,
+                revert: Revert::default(),
+            }
+        }
+
+        /// Apply aggregated changes to the vector
+        pub fn commit(self) {
+            // Commit fields in the inverse order
+            self.blocks.commit();
+            self.revert.commit();
+        }
+
+        /// Get mutable access to the item at the index
+        pub fn get_mut(&mut self, index: usize) -> Option<&mut V> {
+            get_mut(&mut self.blocks, &mut self.revert, index)
+        }
+
+        /// Append item to the end of the vector
+        pub fn push(&mut self, value: V) {
+            push(&mut self.blocks, &mut self.revert, value)
+        }
+
+        /// Remove the last item from the vector
+        pub fn pop(&mut self) -> Option<V> {
+            pop(&mut self.blocks, &mut self.revert)
+        }
+
+        /// Shorten the vector to the `len` items, has no effect if vector is already shorter
+        pub fn truncate(&mut self, len: usize) {
+            while self.blocks.len() > len {
+                pop(&mut self.blocks, &mut self.revert);
+            }
+        }
+    }
+
+    impl<V: Value> StorageVecReadOnly<V> for Block<'_, V> {
+        fn get(&self, index: usize) -> Option<&V> {
+            self.blocks.get(&index)
+        }
+
+        fn iter(&self) -> Iter<'_, V> {
+            Iter {
+                iter: self.blocks.range(..),
+            }
+        }
+
+        fn range(&self, bounds: impl RangeBounds<usize>) -> Iter<'_, V> {
+            Iter {
+                iter: self.blocks.range(bounds),
+            }
+        }
+
+        fn len(&self) -> usize {
+            self.blocks.len()
+        }
+    }
+
+    /// Part of block's aggregated changes which applied or aborted at the same time
+    pub struct Transaction<'block, 'store, V: Value> {
+        pub(crate) revert: Revert<V>,
+        pub(crate) block: &'block mut Block<'store, V>,
+    }
+
+    impl<'block, 'store: 'block, V: Value> Transaction<'block, 'store, V> {
+        /// Apply aggregated changes of [`Transaction`] to the [`Block`]
+        pub fn apply(mut self) {
+            let revert = core::mem::take(&mut self.revert);
+            let block_revert = &mut *self.block.revert;
+            // Length at the start of transaction is the block's previous length if block didn't change it yet
+            let prev_len = match revert.len {
+                Some(len) => *block_revert.len.get_or_insert(len),
+                None => block_revert.prev_len(self.block.blocks.len()),
+            };
+            for (index, value) in revert.items {
+                if index < prev_len {
+                    block_revert.items.entry(index).or_insert(value);
+                }
+            }
+        }
+
+        /// Get mutable access to the item at the index
+        pub fn get_mut(&mut self, index: usize) -> Option<&mut V> {
+            get_mut(&mut self.block.blocks, &mut self.revert, index)
+        }
+
+        /// Append item to the end of the vector
+        pub fn push(&mut self, value: V) {
+            push(&mut self.block.blocks, &mut self.revert, value)
+        }
+
+        /// Remove the last item from the vector
+        pub fn pop(&mut self) -> Option<V> {
+            pop(&mut self.block.blocks, &mut self.revert)
+        }
+
+        /// Shorten the vector to the `len` items, has no effect if vector is already shorter
+        pub fn truncate(&mut self, len: usize) {
+            while self.block.blocks.len() > len {
+                pop(&mut self.block.blocks, &mut self.revert);
+            }
+        }
+    }
+
+    impl<V: Value> StorageVecReadOnly<V> for Transaction<'_, '_, V> {
+        fn get(&self, index: usize) -> Option<&V> {
+            self.block.get(index)
+        }
+
+        fn iter(&self) -> Iter<'_, V> {
+            self.block.iter()
+        }
+
+        fn range(&self, bounds: impl RangeBounds<usize>) -> Iter<'_, V> {
+            self.block.range(bounds)
+        }
+
+        fn len(&self) -> usize {
+            self.block.len()
+        }
+    }
+
+    impl<'block, 'store: 'block, V: Value> Drop for Transaction<'block, 'store, V> {
+        fn drop(&mut self) {
+            // revert changes made so far by current transaction
+            // if transaction was applied revert would be empty
+            core::mem::take(&mut self.revert).revert(&mut self.block.blocks);
+        }
+    }
+
+    fn get_mut<'a, V: Value>(
+        blocks: &'a mut BptreeMapWriteTxn<'_, usize, V>,
+        revert: &mut Revert<V>,
+        index: usize,
+    ) -> Option<&'a mut V> {
+        let len = blocks.len();
+        blocks
+            .get_mut(&index)
+            .inspect(|value| revert.record_item(len, index, value))
+    }
+
+    fn push<V: Value>(
+        blocks: &mut BptreeMapWriteTxn<'_, usize, V>,
+        revert: &mut Revert<V>,
+        value: V,
+    ) {
+        let len = blocks.len();
+        revert.record_len(len);
+        blocks.insert(len, value);
+    }
+
+    fn pop<V: Value>(
+        blocks: &mut BptreeMapWriteTxn<'_, usize, V>,
+        revert: &mut Revert<V>,
+    ) -> Option<V> {
+        let len = blocks.len();
+        let index = len.checked_sub(1)?;
+        let value = blocks.remove(&index)?;
+        revert.record_item(len, index, &value);
+        revert.record_len(len);
+        Some(value)
+    }
+}
+pub use block::{Block, Transaction};
+
+mod iter {
+    use super::*;
+
+    /// Iterate over items in block, view or transaction
+    pub struct Iter<'slf, V: Value> {
+        pub(crate) iter: concread::internals::bptree::iter::RangeIter<'slf, usize, V>,
+    }
+
+    impl<'slf, V: Value> Iterator for Iter<'slf, V> {
+        type Item = &'slf V;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            self.iter.next().map(|(_, value)| value)
+        }
+    }
+}
+pub use iter::Iter;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::proptest;
+
+    #[test]
+    fn push_pop() {
+        let vec = StorageVec::<u64>::new();
+
+        let view0 = vec.view();
+
+        {
+            let mut block = vec.block();
+            for i in 0..5 {
+                block.push(i);
+            }
+            block.commit();
+        }
+
+        let view1 = vec.view();
+
+        {
+            let mut block = vec.block();
+            assert_eq!(block.pop(), Some(4));
+            assert_eq!(block.pop(), Some(3));
+            block.push(5);
+            block.commit();
+        }
+
+        let view2 = vec.view();
+
+        assert!(view0.is_empty());
+        assert!(view1.iter().copied().eq(0..5));
+        assert!(view2.iter().copied().eq([0, 1, 2, 5]));
+        assert_eq!(view2.last(), Some(&5));
+        assert_eq!(view2.get(3), Some(&5));
+        assert_eq!(view2.get(4), None);
+        assert!(view2.range(1..3).copied().eq([1, 2]));
+    }
+
+    #[test]
+    fn transaction_step() {
+        let vec = StorageVec::<u64>::new();
+
+        let mut block = vec.block();
+
+        // Successful transaction
+        {
+            let mut transaction = block.transaction();
+            transaction.push(0);
+            transaction.push(1);
+            transaction.apply();
+        }
+
+        // Aborted step
+        {
+            let mut transaction = block.transaction();
+            transaction.truncate(0);
+            transaction.push(2);
+            *transaction.get_mut(0).expect("pushed above") = 3;
+        }
+
+        // Check that aborted transaction changes don't visible for subsequent transactions
+        {
+            let transaction = block.transaction();
+            assert!(transaction.iter().copied().eq([0, 1]));
+        }
+
+        block.commit();
+
+        let view = vec.view();
+        assert!(view.iter().copied().eq([0, 1]));
+    }
+
+    #[test]
+    fn revert() {
+        let vec = (0..5).collect::<StorageVec<u64>>();
+
+        {
+            let mut block = vec.block();
+            block.truncate(2);
+            *block.get_mut(0).expect("item exists") = 10;
+            block.push(20);
+            block.push(30);
+            block.commit();
+        }
+
+        // Only the old length and items which existed before the block are recorded
+        {
+            let revert = vec.revert.read();
+            assert_eq!(revert.len, Some(5));
+            assert_eq!(
+                revert
+                    .items
+                    .iter()
+                    .map(|(i, v)| (*i, *v))
+                    .collect::<Vec<_>>(),
+                [(0, 0), (2, 2), (3, 3), (4, 4)]
+            );
+        }
+
+        let view1 = vec.view();
+
+        vec.block_and_revert().commit();
+
+        let view2 = vec.view();
+
+        assert!(view1.iter().copied().eq([10, 1, 20, 30]));
+        assert!(view2.iter().copied().eq(0..5));
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Push(u64),
+        Pop,
+        Set(usize, u64),
+        Truncate(usize),
+    }
+
+    fn op() -> impl proptest::strategy::Strategy<Value = Op> {
+        use proptest::prelude::*;
+
+        prop_oneof![
+            any::<u64>().prop_map(Op::Push),
+            Just(Op::Pop),
+            (0..16_usize, any::<u64>()).prop_map(|(i, v)| Op::Set(i, v)),
+            (0..16_usize).prop_map(Op::Truncate),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn consistent_with_vec(
+            blocks in proptest::collection::vec(
+                proptest::collection::vec((proptest::bool::ANY, proptest::collection::vec(op(), 0..8)), 0..4),
+                0..8,
+            ),
+            revert: bool,
+        ) {
+            let storage = StorageVec::<u64>::new();
+            let mut vec = Vec::new();
+            let mut prev = Vec::new();
+
+            for transactions in blocks {
+                let mut block = storage.block();
+                prev = vec.clone();
+
+                for (applied, ops) in transactions {
+                    let mut transaction = block.transaction();
+                    let mut tx_vec = vec.clone();
+                    for op in ops {
+                        match op {
+                            Op::Push(v) => {
+                                transaction.push(v);
+                                tx_vec.push(v);
+                            }
+                            Op::Pop => assert_eq!(transaction.pop(), tx_vec.pop()),
+                            Op::Set(i, v) => {
+                                if let Some(value) = transaction.get_mut(i) {
+                                    *value = v;
+                                }
+                                if let Some(value) = tx_vec.get_mut(i) {
+                                    *value = v;
+                                }
+                            }
+                            Op::Truncate(len) => {
+                                transaction.truncate(len);
+                                tx_vec.truncate(len);
+                            }
+                        }
+                    }
+                    if applied {
+                        transaction.apply();
+                        vec = tx_vec;
+                    }
+                }
+
+                block.commit();
+            }
+
+            if revert {
+                storage.block_and_revert().commit();
+                vec = prev;
+            }
+
+            let view = storage.view();
+            assert!(view.iter().eq(vec.iter()));
+            assert_eq!(view.len(), vec.len());
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Version of the storage published together with its committed state
+//!
+//! Version is a sequence counter: it's odd while a commit is being published and even otherwise.
+//! Readers open their read transactions and check that the counter didn't change meanwhile,
+//! retrying otherwise, so views never take a lock and never observe half of a commit.
+
+use core::hint::spin_loop;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+
+/// Amount of blocks committed to the storage
+///
+/// Public only to appear in sealed traits, module is private so it can't be named outside of the crate.
+#[derive(Debug)]
+pub struct Version(AtomicU64);
+
+impl Version {
+    pub(crate) fn new(version: u64) -> Self {
+        Self(AtomicU64::new(version * 2))
+    }
+
+    /// Current version, waits for the commit being published
+    pub(crate) fn get(&self) -> u64 {
+        self.read(|version| version)
+    }
+
+    /// Current version, caller must have exclusive access to the storage
+    pub(crate) fn get_mut(&mut self) -> u64 {
+        *self.0.get_mut() / 2
+    }
+
+    /// Run `read` with the current version until no commit is published concurrently
+    ///
+    /// Result of the successful attempt is returned, results of the others are dropped.
+    pub(crate) fn read<T>(&self, mut read: impl FnMut(u64) -> T) -> T {
+        read_all([self], |[version]| read(version))
+    }
+
+    /// Start publishing commit, readers retry until returned guard is dropped
+    pub(crate) fn publish(&self) -> Publish<'_> {
+        let sequence = self.0.fetch_add(1, Ordering::Acquire);
+        debug_assert!(
+            sequence.is_multiple_of(2),
+            "commits are published one at a time"
+        );
+        fence(Ordering::Release);
+        Publish {
+            sequence: &self.0,
+            version: sequence / 2,
+        }
+    }
+}
+
+/// Commit being published, next version is set through [`Publish::version`]
+///
+/// Publication ends on drop even if the commit panicked, so readers never wait forever.
+pub(crate) struct Publish<'a> {
+    sequence: &'a AtomicU64,
+    version: u64,
+}
+
+impl Publish<'_> {
+    pub(crate) fn version(&mut self) -> &mut u64 {
+        &mut self.version
+    }
+}
+
+impl Drop for Publish<'_> {
+    fn drop(&mut self) {
+        self.sequence.store(self.version * 2, Ordering::Release);
+    }
+}
+
+/// Run `read` with current versions of several storages until no commit to any of them
+/// is published concurrently
+pub(crate) fn read_all<T, const N: usize>(
+    versions: [&Version; N],
+    mut read: impl FnMut([u64; N]) -> T,
+) -> T {
+    loop {
+        let sequences = versions.map(|version| version.0.load(Ordering::Acquire));
+        if sequences.iter().all(|sequence| sequence.is_multiple_of(2)) {
+            let result = read(sequences.map(|sequence| sequence / 2));
+            fence(Ordering::Acquire);
+            if versions
+                .iter()
+                .zip(sequences)
+                .all(|(version, sequence)| version.0.load(Ordering::Relaxed) == sequence)
+            {
+                return result;
+            }
+        }
+        spin_loop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use super::*;
+
+    #[test]
+    fn publish() {
+        let version = Version::new(3);
+        {
+            let mut publish = version.publish();
+            assert_eq!(*publish.version(), 3);
+            *publish.version() += 1;
+        }
+        assert_eq!(version.get(), 4);
+
+        // Publication ends if commit panics, so readers don't wait forever
+        std::panic::catch_unwind(|| {
+            let _publish = version.publish();
+            panic!("commit failed");
+        })
+        .expect_err("commit panicked");
+        assert_eq!(version.get(), 4);
+    }
+
+    #[test]
+    fn read_retries() {
+        const COMMITS: u64 = 1_000;
+
+        let version = Version::new(0);
+        // Pair of values which writer keeps equal to the version outside of publication
+        let values = [AtomicU64::new(0), AtomicU64::new(0)];
+        let done = AtomicBool::new(false);
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for i in 1..=COMMITS {
+                    let mut publish = version.publish();
+                    values[0].store(i, Ordering::Relaxed);
+                    std::thread::yield_now();
+                    values[1].store(i, Ordering::Relaxed);
+                    *publish.version() = i;
+                }
+                done.store(true, Ordering::Relaxed);
+            });
+
+            while !done.load(Ordering::Relaxed) {
+                let (version, first, second) = version.read(|version| {
+                    let first = values[0].load(Ordering::Relaxed);
+                    let second = values[1].load(Ordering::Relaxed);
+                    (version, first, second)
+                });
+                assert_eq!((first, second), (version, version));
+            }
+        });
+    }
+}